@@ -0,0 +1,4 @@
+-- Client private keys, sealed with the server's encryption key.
+-- Clients created before this migration keep NULL: the server never held
+-- their key, so their configs are rendered with a placeholder instead.
+ALTER TABLE clients ADD COLUMN private_key TEXT;
@@ -0,0 +1,3 @@
+-- What happens to a client once `expires_at` has passed.
+ALTER TABLE clients ADD COLUMN expiry_action TEXT NOT NULL DEFAULT 'disable'
+  CHECK (expiry_action IN ('disable', 'delete'));
@@ -0,0 +1,2 @@
+-- Expiry of the token in `one_time_link`; the token is cleared once used.
+ALTER TABLE clients ADD COLUMN one_time_link_expires_at TEXT;
@@ -0,0 +1,7 @@
+-- Per-client overrides of the global client config. NULL falls back to the
+-- global default.
+ALTER TABLE clients ADD COLUMN dns TEXT;
+ALTER TABLE clients ADD COLUMN allowed_ips TEXT;
+ALTER TABLE clients ADD COLUMN mtu INTEGER;
+ALTER TABLE clients ADD COLUMN persistent_keepalive INTEGER;
+ALTER TABLE clients ADD COLUMN no_preshared_key INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- Comma-separated CIDRs behind a client (e.g. a branch office LAN) that the
+-- server routes to that peer in addition to its tunnel addresses.
+ALTER TABLE clients ADD COLUMN server_allowed_ips TEXT;
@@ -0,0 +1,8 @@
+-- Clients belong to one of several WireGuard interfaces; existing clients
+-- move to the first interface.
+ALTER TABLE clients ADD COLUMN interface_id TEXT REFERENCES interfaces(id);
+UPDATE clients SET interface_id = (SELECT id FROM interfaces ORDER BY rowid LIMIT 1);
+CREATE INDEX IF NOT EXISTS idx_clients_interface_id ON clients(interface_id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_interfaces_name ON interfaces(name);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_interfaces_listen_port ON interfaces(listen_port);
@@ -0,0 +1,2 @@
+-- Accounts can be disabled without deleting them.
+ALTER TABLE users ADD COLUMN enabled INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,3 @@
+-- admin, operator or viewer. Existing accounts had full access, so they
+-- become admins.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'admin';
@@ -0,0 +1,11 @@
+-- TOTP secrets are stored sealed and base32-encoded. A new secret waits in
+-- totp_pending_secret until the user confirms a code from it.
+ALTER TABLE users ADD COLUMN totp_pending_secret TEXT;
+
+-- Single-use recovery codes, stored as SHA-256 hashes. Used codes are deleted.
+CREATE TABLE IF NOT EXISTS recovery_codes (
+  id        INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id   INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  code_hash TEXT    NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id ON recovery_codes(user_id);
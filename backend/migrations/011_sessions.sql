@@ -0,0 +1,13 @@
+-- Login sessions survive restarts. `id` is the SHA-256 of the cookie token,
+-- so a leaked database doesn't hand out live sessions.
+CREATE TABLE IF NOT EXISTS sessions (
+  id           TEXT PRIMARY KEY,
+  username     TEXT NOT NULL,
+  role         TEXT NOT NULL,
+  created_at   TEXT NOT NULL,
+  last_seen_at TEXT NOT NULL,
+  expires_at   TEXT NOT NULL,
+  ip           TEXT,
+  user_agent   TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_sessions_username ON sessions(username);
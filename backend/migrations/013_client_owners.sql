@@ -0,0 +1,4 @@
+-- Clients can belong to a user, who then manages them from the self-service
+-- portal. Deleting the user keeps the client, unowned.
+ALTER TABLE clients ADD COLUMN owner_user_id INTEGER REFERENCES users(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS idx_clients_owner_user_id ON clients(owner_user_id);
@@ -0,0 +1,15 @@
+-- Who changed what. `before` and `after` are JSON snapshots of the target
+-- with secrets removed; `actor` is NULL for the server itself.
+CREATE TABLE IF NOT EXISTS audit_log (
+  id         INTEGER PRIMARY KEY AUTOINCREMENT,
+  created_at TEXT NOT NULL,
+  actor      TEXT,
+  action     TEXT NOT NULL,
+  target_id  TEXT,
+  before     TEXT,
+  after      TEXT,
+  ip         TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_audit_log_created_at ON audit_log(created_at);
+CREATE INDEX IF NOT EXISTS idx_audit_log_actor ON audit_log(actor);
+CREATE INDEX IF NOT EXISTS idx_audit_log_action ON audit_log(action);
@@ -0,0 +1,4 @@
+-- Interfaces can stop their clients reaching each other. Exempt clients
+-- (e.g. a shared printer) stay reachable by every client.
+ALTER TABLE interfaces ADD COLUMN isolate_clients INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE clients ADD COLUMN isolation_exempt INTEGER NOT NULL DEFAULT 0;
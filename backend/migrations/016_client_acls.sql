@@ -0,0 +1,14 @@
+-- Per-client firewall rules over forwarded traffic, checked in `position`
+-- order. A client without rules is unrestricted; one with rules may only
+-- reach what they allow.
+CREATE TABLE IF NOT EXISTS client_acl_rules (
+  id        INTEGER PRIMARY KEY AUTOINCREMENT,
+  client_id TEXT    NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+  position  INTEGER NOT NULL,
+  action    TEXT    NOT NULL,
+  cidr      TEXT    NOT NULL,
+  protocol  TEXT,
+  port_from INTEGER,
+  port_to   INTEGER
+);
+CREATE INDEX IF NOT EXISTS idx_client_acl_rules_client_id ON client_acl_rules(client_id);
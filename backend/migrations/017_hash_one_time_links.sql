@@ -0,0 +1,4 @@
+-- One-time links are now stored as the SHA-256 of their token, and the
+-- download URL (which contains the token) is no longer kept. Links issued
+-- before this can't be hashed in SQL, so they are withdrawn.
+UPDATE clients SET one_time_link = NULL, download_url = NULL, one_time_link_expires_at = NULL;
@@ -0,0 +1,7 @@
+-- SSO logins are tied to accounts by the provider's issuer and subject
+-- (`sub`), the only unique and stable identity in an ID token; usernames
+-- can be edited at many providers. Set when SSO provisions the account or
+-- by an admin.
+ALTER TABLE users ADD COLUMN oidc_issuer TEXT;
+ALTER TABLE users ADD COLUMN oidc_subject TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_oidc ON users(oidc_issuer, oidc_subject);
@@ -0,0 +1,4 @@
+-- The last TOTP time step (Unix time / 30) a user logged in with. Codes for
+-- that step or earlier are refused, so an observed code can't be replayed.
+-- Reset whenever the secret changes.
+ALTER TABLE users ADD COLUMN totp_last_step INTEGER;
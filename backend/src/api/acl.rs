@@ -0,0 +1,68 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+
+use crate::audit::Actor;
+use crate::models::acl::AclRule;
+use crate::{error::AppError, validate, AppState};
+
+#[derive(Deserialize)]
+pub struct UpdateAclRequest {
+    /// Checked in order; the first match decides. Empty lifts the ACL.
+    pub rules: Vec<AclRule>,
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    client_exists(&state, &id).await?;
+    let rules = crate::db::acl::list(&state.db, &id).await?;
+    Ok(Json(serde_json::json!({ "rules": rules })))
+}
+
+/// Replace a client's ACL and reload the firewall rules. Unlike other NAT
+/// refreshes, a failure is an error here: the client would go unrestricted.
+pub async fn update(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(id): Path<String>,
+    Json(body): Json<UpdateAclRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    client_exists(&state, &id).await?;
+    let rules = body
+        .rules
+        .into_iter()
+        .map(validate::acl_rule)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(AppError::BadRequest)?;
+
+    let before = crate::db::acl::list(&state.db, &id).await?;
+    crate::db::acl::replace(&state.db, &id, &rules).await?;
+    crate::network::refresh_nat(&state.db, &state.uplinks)
+        .await
+        .context("ACL saved, but the firewall rules could not be reloaded")?;
+
+    state
+        .audit
+        .record(
+            &actor,
+            "client.acl.update",
+            Some(&id),
+            Some(serde_json::json!({ "rules": before })),
+            Some(serde_json::json!({ "rules": rules })),
+        )
+        .await;
+    Ok(Json(serde_json::json!({ "rules": rules })))
+}
+
+async fn client_exists(state: &AppState, id: &str) -> Result<(), AppError> {
+    crate::db::clients::get(&state.db, id)
+        .await?
+        .map(|_| ())
+        .ok_or(AppError::NotFound)
+}
@@ -0,0 +1,83 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Query, State},
+    http::request::Parts,
+    response::IntoResponse,
+    Json,
+};
+use chrono::DateTime;
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use crate::api::client_ip::client_ip;
+use crate::api::session::Session;
+use crate::audit::Actor;
+use crate::db::audit::AuditFilter;
+use crate::{error::AppError, AppState};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+
+/// The logged-in user (if any) and client IP of a request.
+impl FromRequestParts<AppState> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let ip = ConnectInfo::<SocketAddr>::from_request_parts(parts, state)
+            .await
+            .ok()
+            .map(|ConnectInfo(addr)| {
+                client_ip(addr.ip(), &parts.headers, &state.config.trusted_proxies).to_string()
+            });
+        Ok(Actor {
+            username: parts
+                .extensions
+                .get::<Session>()
+                .map(|s| s.username.clone()),
+            ip,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target_id: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Audit entries, newest first.
+pub async fn list(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let filter = AuditFilter {
+        actor: query.actor,
+        action: query.action,
+        target_id: query.target_id,
+        since: query.since.as_deref().map(parse_bound).transpose()?,
+        until: query.until.as_deref().map(parse_bound).transpose()?,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let (entries, total) = crate::db::audit::list(&state.db, &filter, limit, offset).await?;
+    Ok(Json(serde_json::json!({
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+        "entries": entries,
+    })))
+}
+
+fn parse_bound(ts: &str) -> Result<String, AppError> {
+    DateTime::parse_from_rfc3339(ts)
+        .map(|t| crate::audit::timestamp(t.to_utc()))
+        .map_err(|_| AppError::BadRequest(format!("Invalid RFC 3339 timestamp: {ts}")))
+}
@@ -1,16 +1,23 @@
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, Path, State},
     http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
-    Json,
+    Extension, Json,
 };
-use cookie::{Cookie, SameSite};
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
+use std::net::{IpAddr, SocketAddr};
 
-use crate::api::session::{get_session_id_from_headers, SESSION_COOKIE};
+use crate::api::client_ip::{client_ip, forwarded_https};
+use crate::api::session::{get_session_id_from_headers, session_cookie, Session};
+use crate::audit::Actor;
+use crate::models::api_token::ApiToken;
+use crate::models::user::Role;
 use crate::{error::AppError, AppState};
 
+/// Checked against for unknown and disabled users, so a failed login takes
+/// as long whether or not the username exists. Same cost as real hashes.
+const DUMMY_HASH: &str = "$2b$12$qsPrXBBpgzCGMqwiVes6ieurG38icPsdha0SF26oR9dMR1bevOGd.";
+
 #[derive(Deserialize)]
 pub struct LoginRequest {
     pub username: String,
@@ -22,110 +29,276 @@ pub struct LoginRequest {
 pub struct SessionResponse {
     pub authenticated: bool,
     pub username: Option<String>,
+    pub role: Option<Role>,
+}
+
+impl SessionResponse {
+    fn from_session(session: Option<Session>) -> Self {
+        Self {
+            authenticated: session.is_some(),
+            role: session.as_ref().map(|s| s.role),
+            username: session.map(|s| s.username),
+        }
+    }
+}
+
+/// A session as listed to its owner or an admin.
+#[derive(Serialize)]
+pub struct SessionListItem {
+    #[serde(flatten)]
+    pub session: Session,
+    /// Whether this is the session making the request.
+    pub current: bool,
 }
 
 pub async fn login(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
     Json(body): Json<LoginRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    // Already logged in?
-    if let Some(sid) = get_session_id_from_headers(&headers) {
-        if let Some(username) = state.sessions.read().unwrap().get(&sid).cloned() {
-            return Ok((
-                StatusCode::OK,
-                HeaderMap::new(),
-                Json(SessionResponse {
-                    authenticated: true,
-                    username: Some(username),
-                }),
-            )
-                .into_response());
-        }
-    }
+    let ip = client_ip(addr.ip(), &headers, &state.config.trusted_proxies);
+    let user = crate::db::users::find_by_username(&state.db, &body.username)
+        .await
+        .map_err(AppError::Internal)?
+        .filter(|user| user.enabled != 0);
 
     // INSECURE mode: skip password check
     if !state.config.insecure {
-        let hash = state.config.password_hash.as_deref().unwrap_or("");
-        let valid = bcrypt::verify(&body.password, hash)
+        if let Some(wait) = state.login_throttle.retry_after(ip, &body.username) {
+            metrics::counter!("wg_easy_login_failures_total", "reason" => "throttled").increment(1);
+            let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            return Err(AppError::TooManyRequests(secs));
+        }
+
+        let Some(user) = user.as_ref() else {
+            let _ = bcrypt::verify(&body.password, DUMMY_HASH);
+            return Err(login_failed(&state, ip, &body.username, "password").await);
+        };
+        let valid = bcrypt::verify(&body.password, &user.password_hash)
             .map_err(|e| AppError::Internal(anyhow::anyhow!("bcrypt error: {}", e)))?;
         if !valid {
-            return Err(AppError::Unauthorized);
+            return Err(login_failed(&state, ip, &body.username, "password").await);
         }
 
-        // TOTP check
-        if let Some(user) = crate::db::users::find_by_username(&state.db, &body.username)
-            .await
-            .map_err(AppError::Internal)?
-        {
-            if let Some(secret) = &user.totp_secret {
-                let code = body.totp_code.as_deref().unwrap_or("");
-                let totp = totp_rs::TOTP::new(
-                    totp_rs::Algorithm::SHA1,
-                    6,
-                    1,
-                    30,
-                    secret.as_bytes().to_vec(),
-                    None,
-                    body.username.clone(),
-                )
-                .map_err(|e| AppError::Internal(anyhow::anyhow!("TOTP error: {}", e)))?;
-                if !totp
-                    .check_current(code)
-                    .map_err(|e| AppError::Internal(anyhow::anyhow!("TOTP check: {}", e)))?
-                {
-                    return Err(AppError::Unauthorized);
-                }
-            }
+        // TOTP (or recovery code) check
+        let code = body.totp_code.as_deref().unwrap_or("");
+        let actor = Actor {
+            username: Some(user.username.clone()),
+            ip: Some(ip.to_string()),
+        };
+        if !crate::api::totp::verify_second_factor(&state, &actor, user, code).await? {
+            return Err(login_failed(&state, ip, &body.username, "totp").await);
         }
+        state.login_throttle.record_success(&body.username);
     }
 
-    let session_id = Uuid::new_v4().to_string();
-    state
-        .sessions
-        .write()
-        .unwrap()
-        .insert(session_id.clone(), body.username.clone());
-
-    let cookie = Cookie::build((SESSION_COOKIE, session_id))
-        .http_only(true)
-        .same_site(SameSite::Strict)
-        .path("/")
-        .build();
-
-    let mut resp_headers = HeaderMap::new();
-    resp_headers.insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+    // INSECURE mode lets unknown usernames in as admins
+    let role = user.map_or(Role::Admin, |u| u.role);
+    let (resp_headers, session) =
+        start_session(&state, &body.username, role, addr.ip(), &headers).await?;
 
     Ok((
         StatusCode::OK,
         resp_headers,
-        Json(SessionResponse {
-            authenticated: true,
-            username: Some(body.username),
-        }),
+        Json(SessionResponse::from_session(Some(session))),
     )
         .into_response())
 }
 
-pub async fn logout(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
-    if let Some(sid) = get_session_id_from_headers(&headers) {
-        state.sessions.write().unwrap().remove(&sid);
+/// Create a session for a user who has proved who they are, returning the
+/// `Set-Cookie` header for it. Every successful login goes through here and
+/// is audited as `auth.login`. A session the browser already had is ended,
+/// so a login always gets a fresh session id.
+pub(crate) async fn start_session(
+    state: &AppState,
+    username: &str,
+    role: Role,
+    peer: IpAddr,
+    headers: &HeaderMap,
+) -> Result<(HeaderMap, Session), AppError> {
+    if let Some(old) = get_session_id_from_headers(headers, &state.config) {
+        state.sessions.revoke_token(&old).await?;
+    }
+    let ip = client_ip(peer, headers, &state.config.trusted_proxies);
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let (token, session) = state
+        .sessions
+        .create(username, role, Some(ip.to_string()), user_agent)
+        .await?;
+    let actor = Actor {
+        username: Some(username.to_string()),
+        ip: Some(ip.to_string()),
+    };
+    state
+        .audit
+        .record(
+            &actor,
+            "auth.login",
+            Some(&session.id),
+            None,
+            Some(serde_json::json!({ "role": role })),
+        )
+        .await;
+
+    let cookie = session_cookie(
+        &state.config,
+        token,
+        chrono::Duration::seconds(state.config.session_max_age as i64),
+        forwarded_https(peer, headers, &state.config.trusted_proxies),
+    );
+
+    let mut resp_headers = HeaderMap::new();
+    resp_headers.insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+    Ok((resp_headers, session))
+}
+
+/// Record a failed login for throttling, metrics and the audit log.
+async fn login_failed(
+    state: &AppState,
+    ip: IpAddr,
+    username: &str,
+    reason: &'static str,
+) -> AppError {
+    metrics::counter!("wg_easy_login_failures_total", "reason" => reason).increment(1);
+    let locked_out = state.login_throttle.record_failure(ip, username);
+    if locked_out {
+        metrics::counter!("wg_easy_login_lockouts_total").increment(1);
+        tracing::warn!("Login locked out after repeated failures: user {username:?} from {ip}");
+    }
+    let actor = Actor {
+        username: Some(username.to_string()),
+        ip: Some(ip.to_string()),
+    };
+    state
+        .audit
+        .record(
+            &actor,
+            "auth.login_failed",
+            None,
+            None,
+            Some(serde_json::json!({ "reason": reason, "locked_out": locked_out })),
+        )
+        .await;
+    AppError::Unauthorized
+}
+
+pub async fn logout(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    actor: Actor,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(token) = get_session_id_from_headers(&headers, &state.config) {
+        if let Some(session) = state.sessions.get(&token).await? {
+            state.sessions.revoke_token(&token).await?;
+            state
+                .audit
+                .record(
+                    &actor.as_user(&session.username),
+                    "auth.logout",
+                    Some(&session.id),
+                    None,
+                    None,
+                )
+                .await;
+        }
     }
-    let cookie = Cookie::build((SESSION_COOKIE, ""))
-        .http_only(true)
-        .path("/")
-        .max_age(cookie::time::Duration::ZERO)
-        .build();
+    let cookie = session_cookie(
+        &state.config,
+        String::new(),
+        chrono::Duration::zero(),
+        forwarded_https(addr.ip(), &headers, &state.config.trusted_proxies),
+    );
     let mut resp_headers = HeaderMap::new();
     resp_headers.insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
-    (StatusCode::NO_CONTENT, resp_headers)
+    Ok((StatusCode::NO_CONTENT, resp_headers))
+}
+
+pub async fn check(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let session = match get_session_id_from_headers(&headers, &state.config) {
+        Some(token) => state.sessions.get(&token).await?,
+        None => None,
+    };
+    Ok(Json(SessionResponse::from_session(session)))
+}
+
+/// The caller's own sessions.
+pub async fn list_own_sessions(
+    State(state): State<AppState>,
+    Extension(current): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    let sessions = crate::db::sessions::list_for_user(&state.db, &current.username).await?;
+    Ok(Json(list_items(sessions, &current)))
+}
+
+/// End one of the caller's sessions. API tokens can't log their owner out;
+/// this needs a login session.
+pub async fn revoke_own_session(
+    State(state): State<AppState>,
+    Extension(current): Extension<Session>,
+    auth_token: Option<Extension<ApiToken>>,
+    actor: Actor,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    if auth_token.is_some() {
+        return Err(AppError::Forbidden);
+    }
+    let session = crate::db::sessions::get(&state.db, &id)
+        .await?
+        .filter(|s| s.username == current.username)
+        .ok_or(AppError::NotFound)?;
+    state.sessions.revoke(&session.id).await?;
+    state
+        .audit
+        .record(&actor, "session.revoke", Some(&session.id), None, None)
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Every user's sessions (admin only).
+pub async fn list_all_sessions(
+    State(state): State<AppState>,
+    Extension(current): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    let sessions = crate::db::sessions::list(&state.db).await?;
+    Ok(Json(list_items(sessions, &current)))
+}
+
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let session = crate::db::sessions::get(&state.db, &id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    state.sessions.revoke(&session.id).await?;
+    state
+        .audit
+        .record(
+            &actor,
+            "session.revoke",
+            Some(&session.id),
+            Some(serde_json::json!({ "username": session.username })),
+            None,
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
 }
 
-pub async fn check(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
-    let username = get_session_id_from_headers(&headers)
-        .and_then(|sid| state.sessions.read().unwrap().get(&sid).cloned());
-    Json(SessionResponse {
-        authenticated: username.is_some(),
-        username,
-    })
+fn list_items(sessions: Vec<Session>, current: &Session) -> Vec<SessionListItem> {
+    sessions
+        .into_iter()
+        .map(|session| SessionListItem {
+            current: session.id == current.id,
+            session,
+        })
+        .collect()
 }
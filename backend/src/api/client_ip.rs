@@ -0,0 +1,149 @@
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+/// The client's address. When the connection comes from a trusted reverse
+/// proxy, `X-Forwarded-For` is read right to left, skipping further trusted
+/// hops; `X-Real-IP` is used if there is no `X-Forwarded-For`. Headers from
+/// untrusted peers are ignored, since anyone can set them.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> IpAddr {
+    let peer = peer.to_canonical();
+    if !is_trusted(peer, trusted) {
+        return peer;
+    }
+
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+    if forwarded.is_empty() {
+        return headers
+            .get("x-real-ip")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_addr)
+            .unwrap_or(peer);
+    }
+
+    let mut client = peer;
+    for hop in forwarded.iter().rev() {
+        let Some(ip) = parse_addr(hop) else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip, trusted) {
+            break;
+        }
+    }
+    client
+}
+
+/// Whether a trusted reverse proxy reports, through `X-Forwarded-Proto`, that
+/// the client connected over HTTPS.
+pub fn forwarded_https(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> bool {
+    is_trusted(peer, trusted)
+        && first_value(headers, "x-forwarded-proto")
+            .is_some_and(|p| p.eq_ignore_ascii_case("https"))
+}
+
+/// The host the client asked for: `X-Forwarded-Host` from a trusted proxy,
+/// which may rewrite `Host`, otherwise `Host`.
+pub fn request_host(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> Option<String> {
+    is_trusted(peer, trusted)
+        .then(|| first_value(headers, "x-forwarded-host"))
+        .flatten()
+        .or_else(|| first_value(headers, "host"))
+        .map(str::to_lowercase)
+}
+
+fn is_trusted(peer: IpAddr, trusted: &[IpNet]) -> bool {
+    let peer = peer.to_canonical();
+    trusted.iter().any(|net| net.contains(&peer))
+}
+
+/// The client-facing (left-most) value of a possibly comma-separated header.
+fn first_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .next()
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+/// An IP, optionally with a port (`1.2.3.4:5678`, `[::1]:5678`).
+fn parse_addr(value: &str) -> Option<IpAddr> {
+    let value = value.trim();
+    value
+        .parse::<IpAddr>()
+        .or_else(|_| value.parse::<SocketAddr>().map(|a| a.ip()))
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let h = headers(&[("x-forwarded-for", "203.0.113.9")]);
+        let peer: IpAddr = "198.51.100.1".parse().unwrap();
+        assert_eq!(client_ip(peer, &h, &[]), peer);
+    }
+
+    #[test]
+    fn test_trusted_proxy_chain() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+
+        // Spoofed left-most entry is skipped in favour of the first untrusted hop
+        let h = headers(&[("x-forwarded-for", "1.1.1.1, 203.0.113.9, 10.0.0.3")]);
+        assert_eq!(
+            client_ip(peer, &h, &trusted),
+            "203.0.113.9".parse::<IpAddr>().unwrap()
+        );
+
+        let h = headers(&[("x-real-ip", "[2001:db8::1]:443")]);
+        assert_eq!(
+            client_ip(peer, &h, &trusted),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+
+        assert_eq!(client_ip(peer, &HeaderMap::new(), &trusted), peer);
+    }
+
+    #[test]
+    fn test_forwarded_proto_and_host() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let h = headers(&[
+            ("host", "127.0.0.1:51821"),
+            ("x-forwarded-host", "VPN.example.com"),
+            ("x-forwarded-proto", "https, http"),
+        ]);
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let direct: IpAddr = "198.51.100.1".parse().unwrap();
+        assert!(forwarded_https(proxy, &h, &trusted));
+        assert!(!forwarded_https(direct, &h, &trusted));
+        assert_eq!(
+            request_host(proxy, &h, &trusted).as_deref(),
+            Some("vpn.example.com")
+        );
+        assert_eq!(
+            request_host(direct, &h, &trusted).as_deref(),
+            Some("127.0.0.1:51821")
+        );
+    }
+}
@@ -1,28 +1,77 @@
+use anyhow::Context as _;
 use axum::{
     extract::{Path, State},
     http::{header, StatusCode},
     response::IntoResponse,
     Json,
 };
-use chrono::Utc;
-use ipnet::Ipv4Net;
-use serde::Deserialize;
-use std::net::Ipv4Addr;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use rand::RngCore;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::net::IpAddr;
 use uuid::Uuid;
+use wireguard_control::Key;
 
-use crate::wireguard::{keys, peers};
-use crate::{error::AppError, models::client::Client, AppState};
+use crate::api::session::hash_token;
+use crate::audit::{snapshot, Actor};
+use crate::db::Db;
+use crate::models::client::{expiry_passed, Client, EXPIRY_DELETE, EXPIRY_DISABLE};
+use crate::models::interface::Interface;
+use crate::wireguard::{interface as wgiface, keys, peers};
+use crate::{error::AppError, validate, AppState};
+
+/// How long a one-time config link stays valid if unused.
+const ONE_TIME_LINK_TTL: chrono::Duration = chrono::Duration::hours(24);
 
 #[derive(Deserialize)]
 pub struct CreateClientRequest {
     pub name: String,
+    /// Public key of a device that generated its own keypair. When set, the
+    /// server never sees the private key.
+    pub public_key: Option<String>,
+    /// Interface to add the client to; defaults to the first interface.
+    pub interface_id: Option<String>,
+    /// User who may manage the client from the self-service portal.
+    pub owner_user_id: Option<i64>,
 }
 
 #[derive(Deserialize)]
 pub struct UpdateClientRequest {
     pub name: Option<String>,
     pub enabled: Option<bool>,
+    /// RFC 3339 timestamp; an empty string clears the expiry.
     pub expires_at: Option<String>,
+    /// `"disable"` or `"delete"`.
+    pub expiry_action: Option<String>,
+    // Per-client overrides: omit to keep, `null` to fall back to the default.
+    #[serde(default, deserialize_with = "nullable")]
+    pub dns: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub allowed_ips: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub mtu: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub persistent_keepalive: Option<Option<i64>>,
+    pub no_preshared_key: Option<bool>,
+    /// Comma-separated subnets behind this client that the server routes to it.
+    #[serde(default, deserialize_with = "nullable")]
+    pub server_allowed_ips: Option<Option<String>>,
+    /// `null` removes the owner.
+    #[serde(default, deserialize_with = "nullable")]
+    pub owner_user_id: Option<Option<i64>>,
+    /// Stay reachable by every client when the interface isolates clients.
+    pub isolation_exempt: Option<bool>,
+}
+
+/// Distinguish an explicit `null` (`Some(None)`) from an omitted field (`None`).
+pub(crate) fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
 
 pub async fn list(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
@@ -34,56 +83,148 @@ pub async fn list(State(state): State<AppState>) -> Result<impl IntoResponse, Ap
 
 pub async fn create(
     State(state): State<AppState>,
+    actor: Actor,
     Json(body): Json<CreateClientRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    let (_private_key, public_key) = keys::generate_keypair();
+    if let Some(owner) = body.owner_user_id {
+        check_owner(&state, owner).await?;
+    }
+    let client = create_client(&state, body, None).await?;
+    state
+        .audit
+        .record(
+            &actor,
+            "client.create",
+            Some(&client.id),
+            None,
+            snapshot(&client),
+        )
+        .await;
+    Ok((StatusCode::CREATED, Json(client)))
+}
+
+/// Allocate addresses and keys for a new client, store it and add its peer.
+/// With `owner_limit`, refuse if its owner already has that many clients.
+pub(crate) async fn create_client(
+    state: &AppState,
+    body: CreateClientRequest,
+    owner_limit: Option<u32>,
+) -> Result<Client, AppError> {
+    let iface = match &body.interface_id {
+        Some(id) => crate::db::interfaces::get(&state.db, id)
+            .await
+            .map_err(AppError::Internal)?
+            .ok_or_else(|| AppError::BadRequest(format!("Unknown interface: {id}")))?,
+        None => crate::db::interfaces::get_default(&state.db)
+            .await
+            .map_err(AppError::Internal)?
+            .ok_or_else(|| AppError::Internal(anyhow::anyhow!("No interface configured")))?,
+    };
+
+    let (public_key, sealed_private_key) = match body.public_key.as_deref() {
+        Some(key) => {
+            let key = Key::from_base64(key.trim())
+                .map_err(|_| AppError::BadRequest("Invalid public key".to_string()))?
+                .to_base64();
+            // Any interface's: the peer would take over that server's identity
+            let servers = crate::db::interfaces::list(&state.db)
+                .await
+                .map_err(AppError::Internal)?;
+            if let Some(server) = servers.iter().find(|i| i.public_key == key) {
+                return Err(AppError::Conflict(format!(
+                    "Public key belongs to interface {}",
+                    server.name
+                )));
+            }
+            (key, None)
+        }
+        None => {
+            let (private_key, public_key) = keys::generate_keypair();
+            (public_key, Some(state.secrets.encrypt(&private_key)?))
+        }
+    };
     let preshared_key = keys::generate_preshared_key();
 
-    // Determine next available IP
-    let used_ips = crate::db::clients::get_used_ips(&state.db)
+    // Determine next available IPs
+    let used_ips = crate::db::clients::get_used_ips(&state.db, &iface.id)
         .await
         .map_err(AppError::Internal)?;
-    let iface = crate::db::interfaces::get(&state.db)
-        .await
-        .map_err(AppError::Internal)?
-        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("No interface configured")))?;
 
     let network: Ipv4Net = iface
         .ipv4_cidr
         .parse()
         .map_err(|e| AppError::Internal(anyhow::anyhow!("Invalid CIDR: {}", e)))?;
 
-    let ip = allocate_ip(&network, &used_ips)
+    let ip = allocate_ip(&IpNet::V4(network), &used_ips)
         .ok_or_else(|| AppError::BadRequest("No available IP addresses".to_string()))?;
 
+    let ipv6 = match &iface.ipv6_cidr {
+        Some(cidr) => {
+            let network: Ipv6Net = cidr
+                .parse()
+                .map_err(|e| AppError::Internal(anyhow::anyhow!("Invalid IPv6 CIDR: {}", e)))?;
+            let used = crate::db::clients::get_used_ipv6(&state.db, &iface.id)
+                .await
+                .map_err(AppError::Internal)?;
+            let ip = allocate_ip(&IpNet::V6(network), &used)
+                .ok_or_else(|| AppError::BadRequest("No available IPv6 addresses".to_string()))?;
+            Some(ip.to_string())
+        }
+        None => None,
+    };
+
     let client = Client {
         id: Uuid::new_v4().to_string(),
+        interface_id: iface.id.clone(),
         name: body.name,
-        public_key: public_key.clone(),
-        preshared_key: preshared_key.clone(),
+        public_key,
+        preshared_key,
+        private_key: sealed_private_key,
         ipv4: ip.to_string(),
-        ipv6: None,
+        ipv6,
         enabled: 1,
         created_at: Utc::now().to_rfc3339(),
         expires_at: None,
-        download_url: None,
+        expiry_action: EXPIRY_DISABLE.to_string(),
+        expired: false,
         one_time_link: None,
+        one_time_link_expires_at: None,
+        dns: None,
+        allowed_ips: None,
+        mtu: None,
+        persistent_keepalive: None,
+        no_preshared_key: 0,
+        server_allowed_ips: None,
+        owner_user_id: body.owner_user_id,
+        isolation_exempt: 0,
     };
 
-    crate::db::clients::create(&state.db, &client)
+    let created = crate::db::clients::create(&state.db, &client, owner_limit)
         .await
-        .map_err(AppError::Internal)?;
+        .map_err(|e| {
+            if crate::db::is_unique_violation(&e) {
+                AppError::Conflict("A client with this public key already exists".to_string())
+            } else {
+                AppError::Internal(e)
+            }
+        })?;
+    if !created {
+        return Err(AppError::Conflict(format!(
+            "You already have {} devices, the most allowed",
+            owner_limit.unwrap_or_default()
+        )));
+    }
 
     // Add peer to kernel
     peers::add_peer(
         &iface.name,
-        &public_key,
-        &preshared_key,
-        &[&format!("{}/32", ip)],
+        &client.public_key,
+        client.active_preshared_key(),
+        &client.peer_allowed_ips(),
     )
     .map_err(AppError::Internal)?;
 
-    Ok((StatusCode::CREATED, Json(client)))
+    Ok(client)
 }
 
 pub async fn get_one(
@@ -99,6 +240,7 @@ pub async fn get_one(
 
 pub async fn update(
     State(state): State<AppState>,
+    actor: Actor,
     Path(id): Path<String>,
     Json(body): Json<UpdateClientRequest>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -106,41 +248,160 @@ pub async fn update(
         .await
         .map_err(AppError::Internal)?
         .ok_or(AppError::NotFound)?;
+    let before = snapshot(&client);
+    let previous_subnets = client.routed_subnets();
 
-    let name = body.name.unwrap_or(client.name.clone());
+    let iface = client_interface(&state, &client).await?;
+    let old_subnets = if client.enabled != 0 {
+        client.routed_subnets()
+    } else {
+        Vec::new()
+    };
+
+    if let Some(name) = body.name {
+        client.name = name;
+    }
+    if let Some(expires_at) = body.expires_at {
+        client.expires_at = match expires_at.as_str() {
+            "" => None,
+            ts => {
+                DateTime::parse_from_rfc3339(ts).map_err(|_| {
+                    AppError::BadRequest("expires_at must be an RFC 3339 timestamp".to_string())
+                })?;
+                Some(expires_at)
+            }
+        };
+        client.expired = expiry_passed(client.expires_at.as_deref(), Utc::now());
+    }
+    if let Some(action) = body.expiry_action {
+        if action != EXPIRY_DISABLE && action != EXPIRY_DELETE {
+            return Err(AppError::BadRequest(
+                "expiry_action must be \"disable\" or \"delete\"".to_string(),
+            ));
+        }
+        client.expiry_action = action;
+    }
+    if let Some(dns) = body.dns {
+        client.dns = dns
+            .map(|v| validate::dns_servers(&v))
+            .transpose()
+            .map_err(AppError::BadRequest)?;
+    }
+    if let Some(allowed_ips) = body.allowed_ips {
+        client.allowed_ips = allowed_ips
+            .map(|v| validate::cidr_list(&v))
+            .transpose()
+            .map_err(AppError::BadRequest)?;
+    }
+    if let Some(mtu) = body.mtu {
+        client.mtu = mtu
+            .map(validate::mtu)
+            .transpose()
+            .map_err(AppError::BadRequest)?;
+    }
+    if let Some(keepalive) = body.persistent_keepalive {
+        client.persistent_keepalive = keepalive
+            .map(validate::persistent_keepalive)
+            .transpose()
+            .map_err(AppError::BadRequest)?;
+    }
+    if let Some(no_psk) = body.no_preshared_key {
+        client.no_preshared_key = no_psk as i64;
+    }
+    if let Some(server_allowed_ips) = body.server_allowed_ips {
+        client.server_allowed_ips = match server_allowed_ips {
+            Some(v) => {
+                let subnets = validate::parse_cidr_list(&v).map_err(AppError::BadRequest)?;
+                check_routed_subnets(&state, &client.id, &subnets).await?;
+                (!subnets.is_empty()).then(|| {
+                    subnets
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                })
+            }
+            None => None,
+        };
+    }
+    if let Some(owner) = body.owner_user_id {
+        if let Some(owner) = owner {
+            check_owner(&state, owner).await?;
+        }
+        client.owner_user_id = owner;
+    }
+    let exempt_changed = body
+        .isolation_exempt
+        .is_some_and(|exempt| exempt != (client.isolation_exempt != 0));
+    if let Some(exempt) = body.isolation_exempt {
+        client.isolation_exempt = exempt as i64;
+    }
+    // Other edits, e.g. a rename, still work on an expired client
+    if body.enabled == Some(true) && client.expired {
+        return Err(AppError::BadRequest(
+            "Client has expired; extend expires_at to enable it".to_string(),
+        ));
+    }
     let enabled = body.enabled.unwrap_or(client.enabled != 0);
-    let expires_at = body.expires_at.as_deref().or(client.expires_at.as_deref());
+    client.enabled = enabled as i64;
 
-    crate::db::clients::update(&state.db, &id, &name, enabled, expires_at)
+    crate::db::clients::update(&state.db, &client)
         .await
         .map_err(AppError::Internal)?;
 
-    client.name = name;
-    client.enabled = enabled as i64;
-    client.expires_at = body.expires_at.or(client.expires_at);
-
-    let iface = crate::db::interfaces::get(&state.db)
-        .await
-        .map_err(AppError::Internal)?
-        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("No interface configured")))?;
+    // An ACL covers the routed subnets too; reload it before the peer may
+    // send from new ones
+    let acl_changed = client.routed_subnets() != previous_subnets
+        && !crate::db::acl::list(&state.db, &id)
+            .await
+            .map_err(AppError::Internal)?
+            .is_empty();
+    if exempt_changed || acl_changed {
+        crate::api::interface::refresh_nat(&state)
+            .await
+            .context("Client saved, but the firewall rules could not be reloaded")?;
+    }
 
+    let new_subnets = if enabled {
+        client.routed_subnets()
+    } else {
+        Vec::new()
+    };
     if enabled {
         peers::add_peer(
             &iface.name,
             &client.public_key,
-            &client.preshared_key,
-            &[&format!("{}/32", client.ipv4)],
+            client.active_preshared_key(),
+            &client.peer_allowed_ips(),
         )
         .map_err(AppError::Internal)?;
     } else {
         peers::remove_peer(&iface.name, &client.public_key).map_err(AppError::Internal)?;
     }
+    let removed: Vec<IpNet> = old_subnets
+        .into_iter()
+        .filter(|net| !new_subnets.contains(net))
+        .collect();
+    wgiface::update_routes(&iface.name, &new_subnets, &removed)
+        .await
+        .map_err(AppError::Internal)?;
 
+    state
+        .audit
+        .record(
+            &actor,
+            "client.update",
+            Some(&id),
+            before,
+            snapshot(&client),
+        )
+        .await;
     Ok(Json(client))
 }
 
 pub async fn delete(
     State(state): State<AppState>,
+    actor: Actor,
     Path(id): Path<String>,
 ) -> Result<impl IntoResponse, AppError> {
     let client = crate::db::clients::get(&state.db, &id)
@@ -148,65 +409,116 @@ pub async fn delete(
         .map_err(AppError::Internal)?
         .ok_or(AppError::NotFound)?;
 
-    let iface = crate::db::interfaces::get(&state.db)
+    let iface = client_interface(&state, &client).await?;
+    let has_acl = !crate::db::acl::list(&state.db, &id)
         .await
         .map_err(AppError::Internal)?
-        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("No interface configured")))?;
+        .is_empty();
 
     peers::remove_peer(&iface.name, &client.public_key).map_err(AppError::Internal)?;
+    if client.enabled != 0 {
+        wgiface::update_routes(&iface.name, &[], &client.routed_subnets())
+            .await
+            .map_err(AppError::Internal)?;
+    }
     crate::db::clients::delete(&state.db, &id)
         .await
         .map_err(AppError::Internal)?;
+    // Its addresses may be handed out again, without the exemption or ACL
+    if client.isolation_exempt != 0 || has_acl {
+        crate::api::interface::refresh_nat(&state)
+            .await
+            .context("Client deleted, but the firewall rules could not be reloaded")?;
+    }
 
+    state
+        .audit
+        .record(&actor, "client.delete", Some(&id), snapshot(&client), None)
+        .await;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn enable(
     State(state): State<AppState>,
+    actor: Actor,
     Path(id): Path<String>,
 ) -> Result<impl IntoResponse, AppError> {
-    let client = crate::db::clients::get(&state.db, &id)
+    let mut client = crate::db::clients::get(&state.db, &id)
         .await
         .map_err(AppError::Internal)?
         .ok_or(AppError::NotFound)?;
+    if client.expired {
+        return Err(AppError::BadRequest(
+            "Client has expired; extend expires_at to enable it".to_string(),
+        ));
+    }
+    let before = snapshot(&client);
     crate::db::clients::set_enabled(&state.db, &id, true)
         .await
         .map_err(AppError::Internal)?;
 
-    let iface = crate::db::interfaces::get(&state.db)
-        .await
-        .map_err(AppError::Internal)?
-        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("No interface configured")))?;
+    let iface = client_interface(&state, &client).await?;
     peers::add_peer(
         &iface.name,
         &client.public_key,
-        &client.preshared_key,
-        &[&format!("{}/32", client.ipv4)],
+        client.active_preshared_key(),
+        &client.peer_allowed_ips(),
     )
     .map_err(AppError::Internal)?;
+    wgiface::update_routes(&iface.name, &client.routed_subnets(), &[])
+        .await
+        .map_err(AppError::Internal)?;
 
+    client.enabled = 1;
+    state
+        .audit
+        .record(
+            &actor,
+            "client.enable",
+            Some(&id),
+            before,
+            snapshot(&client),
+        )
+        .await;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn disable(
     State(state): State<AppState>,
+    actor: Actor,
     Path(id): Path<String>,
 ) -> Result<impl IntoResponse, AppError> {
-    let client = crate::db::clients::get(&state.db, &id)
+    let mut client = crate::db::clients::get(&state.db, &id)
         .await
         .map_err(AppError::Internal)?
         .ok_or(AppError::NotFound)?;
-    crate::db::clients::set_enabled(&state.db, &id, false)
+    let before = snapshot(&client);
+    disable_client(&state, &client).await?;
+    client.enabled = 0;
+    state
+        .audit
+        .record(
+            &actor,
+            "client.disable",
+            Some(&id),
+            before,
+            snapshot(&client),
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Mark a client disabled and take its peer and routes off the interface.
+pub(crate) async fn disable_client(state: &AppState, client: &Client) -> Result<(), AppError> {
+    crate::db::clients::set_enabled(&state.db, &client.id, false)
         .await
         .map_err(AppError::Internal)?;
 
-    let iface = crate::db::interfaces::get(&state.db)
-        .await
-        .map_err(AppError::Internal)?
-        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("No interface configured")))?;
+    let iface = client_interface(state, client).await?;
     peers::remove_peer(&iface.name, &client.public_key).map_err(AppError::Internal)?;
-
-    Ok(StatusCode::NO_CONTENT)
+    wgiface::update_routes(&iface.name, &[], &client.routed_subnets())
+        .await
+        .map_err(AppError::Internal)
 }
 
 pub async fn qrcode(
@@ -214,6 +526,10 @@ pub async fn qrcode(
     Path(id): Path<String>,
 ) -> Result<impl IntoResponse, AppError> {
     let conf = build_client_conf(&state, &id).await?;
+    qrcode_svg(&conf)
+}
+
+pub(crate) fn qrcode_svg(conf: &str) -> Result<impl IntoResponse, AppError> {
     let code = qrcode::QrCode::new(conf.as_bytes())
         .map_err(|e| AppError::Internal(anyhow::anyhow!("QR error: {}", e)))?;
     let svg = code.render::<qrcode::render::svg::Color>().build();
@@ -225,7 +541,95 @@ pub async fn download_conf(
     Path(id): Path<String>,
 ) -> Result<impl IntoResponse, AppError> {
     let conf = build_client_conf(&state, &id).await?;
-    Ok((
+    Ok(conf_attachment(conf))
+}
+
+#[derive(Serialize)]
+pub struct OneTimeLinkResponse {
+    #[serde(flatten)]
+    pub client: Client,
+    pub one_time_link: String,
+    pub download_url: String,
+}
+
+/// Mint a random, expiring token that lets anyone download this client's
+/// config once via the public `/cnf/{token}` route.
+pub async fn generate_one_time_link(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut client = crate::db::clients::get(&state.db, &id)
+        .await
+        .map_err(AppError::Internal)?
+        .ok_or(AppError::NotFound)?;
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+    let download_url = format!("/cnf/{token}");
+    let expires_at = (Utc::now() + ONE_TIME_LINK_TTL).to_rfc3339();
+
+    let token_hash = hash_token(&token);
+    crate::db::clients::set_one_time_link(&state.db, &id, &token_hash, &expires_at)
+        .await
+        .map_err(AppError::Internal)?;
+
+    client.one_time_link = Some(token_hash);
+    client.one_time_link_expires_at = Some(expires_at);
+    state
+        .audit
+        .record(&actor, "client.one_time_link.create", Some(&id), None, None)
+        .await;
+    Ok(Json(OneTimeLinkResponse {
+        client,
+        one_time_link: token,
+        download_url,
+    }))
+}
+
+/// Public (unauthenticated) download of a config through a one-time link.
+/// Links of disabled or expired clients don't work, so a link minted before
+/// a device was lost can't still hand out its key.
+pub async fn download_one_time_link(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let token_hash = hash_token(&token);
+    let client = crate::db::clients::find_by_one_time_link(&state.db, &token_hash)
+        .await
+        .map_err(AppError::Internal)?
+        .ok_or(AppError::NotFound)?;
+    if expiry_passed(client.one_time_link_expires_at.as_deref(), Utc::now())
+        || client.enabled == 0
+        || client.expired
+    {
+        return Err(AppError::NotFound);
+    }
+    if !crate::db::clients::consume_one_time_link(&state.db, &client.id, &token_hash)
+        .await
+        .map_err(AppError::Internal)?
+    {
+        return Err(AppError::NotFound);
+    }
+
+    let conf = build_client_conf(&state, &client.id).await?;
+    state
+        .audit
+        .record(
+            &actor,
+            "client.one_time_link.use",
+            Some(&client.id),
+            None,
+            None,
+        )
+        .await;
+    Ok(conf_attachment(conf))
+}
+
+pub(crate) fn conf_attachment(conf: String) -> impl IntoResponse {
+    (
         [
             (header::CONTENT_TYPE, "application/octet-stream"),
             (
@@ -234,54 +638,346 @@ pub async fn download_conf(
             ),
         ],
         conf,
-    ))
+    )
 }
 
-async fn build_client_conf(state: &AppState, id: &str) -> Result<String, AppError> {
+pub(crate) async fn build_client_conf(state: &AppState, id: &str) -> Result<String, AppError> {
     let client = crate::db::clients::get(&state.db, id)
         .await
         .map_err(AppError::Internal)?
         .ok_or(AppError::NotFound)?;
-    let iface = crate::db::interfaces::get(&state.db)
-        .await
-        .map_err(AppError::Internal)?
-        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("No interface configured")))?;
+    let iface = client_interface(state, &client).await?;
 
-    // Generate a temporary private key for the conf (in real usage the client already has it)
-    // We render the conf using tera template
     let mut tera = tera::Tera::default();
     tera.add_raw_template("client.conf", include_str!("../templates/client.conf.tera"))
         .map_err(|e| AppError::Internal(anyhow::anyhow!("Template error: {}", e)))?;
 
+    // `None` for bring-your-own-key clients and clients created before keys
+    // were stored: the template then leaves the key for the device to fill in.
+    let private_key = client
+        .private_key
+        .as_deref()
+        .map(|sealed| state.secrets.decrypt(sealed))
+        .transpose()?;
+
+    // The default interface advertises the configured public port, which may
+    // differ from its listen port behind port forwarding.
+    let settings = state.settings.read().unwrap().clone();
+    let is_default = crate::db::interfaces::get_default(&state.db)
+        .await
+        .map_err(AppError::Internal)?
+        .is_some_and(|default| default.id == iface.id);
+    let server_port = if is_default {
+        i64::from(settings.wg_port)
+    } else {
+        iface.listen_port
+    };
+
     let mut ctx = tera::Context::new();
-    // The private key is generated by the client at setup time and is not stored on the server.
-    // This placeholder reminds users to replace it with their own private key.
-    ctx.insert("private_key", "[REPLACE_WITH_YOUR_PRIVATE_KEY]");
+    ctx.insert("private_key", &private_key);
     ctx.insert("ipv4", &client.ipv4);
     ctx.insert("ipv6", &client.ipv6);
-    ctx.insert("dns", &state.config.wg_default_dns);
+    ctx.insert(
+        "dns",
+        client.dns.as_ref().unwrap_or(&settings.wg_default_dns),
+    );
+    ctx.insert("mtu", &client.mtu.or(settings.wg_mtu.map(i64::from)));
     ctx.insert("server_public_key", &iface.public_key);
-    ctx.insert("preshared_key", &client.preshared_key);
-    ctx.insert("server_host", &state.config.wg_host);
-    ctx.insert("server_port", &state.config.wg_port);
-    ctx.insert("allowed_ips", &state.config.wg_allowed_ips);
+    ctx.insert("preshared_key", &client.active_preshared_key());
+    ctx.insert("server_host", &settings.wg_host);
+    ctx.insert("server_port", &server_port);
+    ctx.insert(
+        "allowed_ips",
+        client
+            .allowed_ips
+            .as_ref()
+            .unwrap_or(&settings.wg_allowed_ips),
+    );
+    ctx.insert(
+        "persistent_keepalive",
+        &client
+            .persistent_keepalive
+            .unwrap_or(i64::from(settings.wg_persistent_keepalive)),
+    );
 
     tera.render("client.conf", &ctx)
         .map_err(|e| AppError::Internal(anyhow::anyhow!("Template render error: {}", e)))
 }
 
-fn allocate_ip(network: &Ipv4Net, used: &[String]) -> Option<Ipv4Addr> {
+/// Give IPv6 addresses to clients that have none, e.g. when an IPv6 CIDR is
+/// configured for the first time.
+pub async fn backfill_ipv6(db: &Db, iface: &Interface) -> anyhow::Result<()> {
+    let Some(cidr) = &iface.ipv6_cidr else {
+        return Ok(());
+    };
+    let network: Ipv6Net = cidr
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid IPv6 CIDR {}: {}", cidr, e))?;
+    let mut used = crate::db::clients::get_used_ipv6(db, &iface.id).await?;
+    for client in crate::db::clients::list_by_interface(db, &iface.id).await? {
+        if client.ipv6.is_some() {
+            continue;
+        }
+        let ip = allocate_ip(&IpNet::V6(network), &used)
+            .ok_or_else(|| anyhow::anyhow!("No available IPv6 addresses"))?
+            .to_string();
+        crate::db::clients::set_ipv6(db, &client.id, &ip).await?;
+        tracing::info!("Assigned {} to client {}", ip, client.name);
+        used.push(ip);
+    }
+    Ok(())
+}
+
+/// Replace the kernel peer list with the enabled clients in the DB.
+pub async fn sync_peers(db: &Db, iface: &Interface) -> anyhow::Result<()> {
+    let clients = crate::db::clients::list_enabled(db, &iface.id).await?;
+    let peer_tuples: Vec<(String, Option<String>, Vec<String>)> = clients
+        .iter()
+        .map(|c| {
+            (
+                c.public_key.clone(),
+                c.active_preshared_key().map(str::to_string),
+                c.peer_allowed_ips(),
+            )
+        })
+        .collect();
+    peers::sync_peers(&iface.name, &peer_tuples)
+}
+
+/// Reject routed subnets that overlap a VPN network or another client's
+/// routed subnets, since the kernel can route each address to only one peer.
+async fn check_routed_subnets(
+    state: &AppState,
+    client_id: &str,
+    subnets: &[IpNet],
+) -> Result<(), AppError> {
+    let mut taken: Vec<(IpNet, String)> = Vec::new();
+    for iface in crate::db::interfaces::list(&state.db)
+        .await
+        .map_err(AppError::Internal)?
+    {
+        for net in iface.networks().map_err(AppError::Internal)? {
+            taken.push((net, format!("the {} network", iface.name)));
+        }
+    }
+    for other in crate::db::clients::list(&state.db)
+        .await
+        .map_err(AppError::Internal)?
+    {
+        if other.id != client_id {
+            for net in other.routed_subnets() {
+                taken.push((net, format!("client {}", other.name)));
+            }
+        }
+    }
+
+    for (i, net) in subnets.iter().enumerate() {
+        if net.trunc() != *net {
+            return Err(AppError::BadRequest(format!(
+                "{net} has host bits set; did you mean {}?",
+                net.trunc()
+            )));
+        }
+        if let Some((_, owner)) = taken.iter().find(|(t, _)| overlaps(net, t)) {
+            return Err(AppError::Conflict(format!("{net} overlaps {owner}")));
+        }
+        if subnets[..i].iter().any(|prev| overlaps(net, prev)) {
+            return Err(AppError::BadRequest(format!("{net} is listed twice")));
+        }
+    }
+    Ok(())
+}
+
+/// Fail with 400 unless `user_id` exists.
+async fn check_owner(state: &AppState, user_id: i64) -> Result<(), AppError> {
+    crate::db::users::get(&state.db, user_id)
+        .await
+        .map_err(AppError::Internal)?
+        .map(|_| ())
+        .ok_or_else(|| AppError::BadRequest(format!("Unknown user: {user_id}")))
+}
+
+/// The interface a client's peer lives on.
+async fn client_interface(state: &AppState, client: &Client) -> Result<Interface, AppError> {
+    crate::db::interfaces::get(&state.db, &client.interface_id)
+        .await
+        .map_err(AppError::Internal)?
+        .ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!(
+                "Interface of client {} not found",
+                client.name
+            ))
+        })
+}
+
+pub(crate) fn overlaps(a: &IpNet, b: &IpNet) -> bool {
+    a.contains(&b.network()) || b.contains(&a.network())
+}
+
+fn allocate_ip(network: &IpNet, used: &[String]) -> Option<IpAddr> {
     // The first usable host in the network is reserved for the WireGuard server itself.
     // e.g. in 10.8.0.0/24, host 10.8.0.1 is the server; clients start at 10.8.0.2.
-    let server_ip = network.hosts().next();
-    for host in network.hosts() {
-        let is_server_ip = server_ip.map_or(false, |s| s == host);
-        if is_server_ip {
-            continue;
+    network
+        .hosts()
+        .filter(|host| !wgiface::is_reserved(network, *host))
+        .find(|host| !used.contains(&host.to_string()))
+}
+
+/// Clients of an interface whose addresses change when its networks move from
+/// `old` to `new`, with the new addresses filled in. Fails if they don't fit.
+pub async fn plan_renumber(
+    db: &Db,
+    old: &Interface,
+    new: &Interface,
+) -> Result<Vec<Client>, AppError> {
+    let before = crate::db::clients::list_by_interface(db, &old.id)
+        .await
+        .map_err(AppError::Internal)?;
+    let mut clients = before.clone();
+    let parse = |cidr: &str| {
+        cidr.parse::<IpNet>()
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Invalid CIDR {}: {}", cidr, e)))
+    };
+
+    if old.ipv4_cidr != new.ipv4_cidr {
+        let (from, to) = (parse(&old.ipv4_cidr)?, parse(&new.ipv4_cidr)?);
+        let addrs = parse_addresses(clients.iter().map(|c| c.ipv4.as_str()))?;
+        let moved = renumber(&from, &to, &addrs).ok_or_else(|| {
+            AppError::BadRequest(format!("{} clients don't fit in {to}", clients.len()))
+        })?;
+        for (client, ip) in clients.iter_mut().zip(moved) {
+            client.ipv4 = ip.to_string();
         }
-        if !used.contains(&host.to_string()) {
-            return Some(host);
+    }
+    if let (Some(old_cidr), Some(new_cidr)) = (&old.ipv6_cidr, &new.ipv6_cidr) {
+        if old_cidr != new_cidr {
+            let (from, to) = (parse(old_cidr)?, parse(new_cidr)?);
+            let with_ipv6: Vec<&mut Client> =
+                clients.iter_mut().filter(|c| c.ipv6.is_some()).collect();
+            let addrs = parse_addresses(with_ipv6.iter().filter_map(|c| c.ipv6.as_deref()))?;
+            let moved = renumber(&from, &to, &addrs).ok_or_else(|| {
+                AppError::BadRequest(format!("{} clients don't fit in {to}", with_ipv6.len()))
+            })?;
+            for (client, ip) in with_ipv6.into_iter().zip(moved) {
+                client.ipv6 = Some(ip.to_string());
+            }
         }
     }
-    None
+
+    Ok(clients
+        .into_iter()
+        .zip(before)
+        .filter(|(after, before)| after.ipv4 != before.ipv4 || after.ipv6 != before.ipv6)
+        .map(|(after, _)| after)
+        .collect())
+}
+
+fn parse_addresses<'a>(addrs: impl Iterator<Item = &'a str>) -> Result<Vec<IpAddr>, AppError> {
+    addrs
+        .map(|a| {
+            a.parse()
+                .map_err(|_| AppError::Internal(anyhow::anyhow!("Invalid client address {}", a)))
+        })
+        .collect()
+}
+
+/// Map addresses from network `old` into `new`. Each address keeps its host
+/// offset where possible (10.8.0.7 in 10.8.0.0/24 becomes 10.9.0.7 in
+/// 10.9.0.0/24); the rest take the lowest free addresses, in order. `None` if
+/// `new` is too small.
+fn renumber(old: &IpNet, new: &IpNet, addrs: &[IpAddr]) -> Option<Vec<IpAddr>> {
+    let mut used = std::collections::HashSet::new();
+    let mut moved: Vec<Option<IpAddr>> = addrs
+        .iter()
+        .map(|addr| {
+            let offset = ip_to_u128(*addr).checked_sub(ip_to_u128(old.network()))?;
+            let candidate = u128_to_ip(new, ip_to_u128(new.network()).checked_add(offset)?)?;
+            (is_client_host(new, candidate) && used.insert(candidate)).then_some(candidate)
+        })
+        .collect();
+    for slot in moved.iter_mut().filter(|slot| slot.is_none()) {
+        let ip = new
+            .hosts()
+            .filter(|host| !wgiface::is_reserved(new, *host))
+            .find(|host| !used.contains(host))?;
+        used.insert(ip);
+        *slot = Some(ip);
+    }
+    moved.into_iter().collect()
+}
+
+/// Whether `ip` is an address `allocate_ip` could hand out in `network`.
+fn is_client_host(network: &IpNet, ip: IpAddr) -> bool {
+    let broadcast =
+        matches!(network, IpNet::V4(_)) && network.prefix_len() < 31 && ip == network.broadcast();
+    network.contains(&ip) && !broadcast && !wgiface::is_reserved(network, ip)
+}
+
+fn ip_to_u128(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u32::from(ip).into(),
+        IpAddr::V6(ip) => ip.into(),
+    }
+}
+
+fn u128_to_ip(network: &IpNet, value: u128) -> Option<IpAddr> {
+    match network {
+        IpNet::V4(_) => u32::try_from(value)
+            .ok()
+            .map(|v| std::net::Ipv4Addr::from(v).into()),
+        IpNet::V6(_) => Some(std::net::Ipv6Addr::from(value).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocate_ip_skips_server() {
+        let net: IpNet = "10.8.0.0/24".parse().unwrap();
+        let ip = allocate_ip(&net, &["10.8.0.2".to_string()]).unwrap();
+        assert_eq!(ip.to_string(), "10.8.0.3");
+    }
+
+    #[test]
+    fn test_allocate_ipv6_skips_network_and_server() {
+        let net: IpNet = "fd42::/64".parse().unwrap();
+        assert_eq!(allocate_ip(&net, &[]).unwrap().to_string(), "fd42::2");
+    }
+
+    #[test]
+    fn test_renumber_keeps_host_offsets() {
+        let old: IpNet = "10.8.0.0/24".parse().unwrap();
+        let new: IpNet = "10.9.0.0/24".parse().unwrap();
+        let addrs = ["10.8.0.2".parse().unwrap(), "10.8.0.7".parse().unwrap()];
+        let moved = renumber(&old, &new, &addrs).unwrap();
+        assert_eq!(
+            moved,
+            [
+                "10.9.0.2".parse::<IpAddr>().unwrap(),
+                "10.9.0.7".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_renumber_into_smaller_network() {
+        let old: IpNet = "10.8.0.0/24".parse().unwrap();
+        let new: IpNet = "10.9.0.0/29".parse().unwrap();
+        let addrs: Vec<IpAddr> = ["10.8.0.3", "10.8.0.200", "10.8.0.7"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        // .7 is the /29 broadcast address, so it moves like .200 does
+        let moved = renumber(&old, &new, &addrs).unwrap();
+        assert_eq!(
+            moved,
+            ["10.9.0.3", "10.9.0.2", "10.9.0.4"]
+                .iter()
+                .map(|a| a.parse::<IpAddr>().unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert!(renumber(&old, &"10.9.0.0/30".parse().unwrap(), &addrs).is_none());
+    }
 }
@@ -1,3 +1,4 @@
+use crate::audit::{snapshot, Actor};
 use crate::{error::AppError, AppState};
 use axum::{extract::State, response::IntoResponse, Json};
 use serde::{Deserialize, Serialize};
@@ -9,37 +10,102 @@ pub struct ConfigResponse {
     pub wg_default_dns: String,
     pub wg_allowed_ips: String,
     pub wg_default_address: String,
+    pub wg_mtu: Option<u16>,
+    pub wg_persistent_keepalive: u16,
+    pub member_max_clients: u32,
 }
 
 #[derive(Deserialize)]
 pub struct UpdateConfigRequest {
+    pub wg_host: Option<String>,
+    pub wg_port: Option<i64>,
     pub wg_default_dns: Option<String>,
     pub wg_allowed_ips: Option<String>,
+    /// `null` falls back to no `MTU` line in client configs.
+    #[serde(default, deserialize_with = "crate::api::clients::nullable")]
+    pub wg_mtu: Option<Option<i64>>,
+    pub wg_persistent_keepalive: Option<i64>,
+    pub member_max_clients: Option<i64>,
 }
 
 pub async fn get_config(State(state): State<AppState>) -> impl IntoResponse {
-    Json(ConfigResponse {
-        wg_host: state.config.wg_host.clone(),
-        wg_port: state.config.wg_port,
-        wg_default_dns: state.config.wg_default_dns.clone(),
-        wg_allowed_ips: state.config.wg_allowed_ips.clone(),
-        wg_default_address: state.config.wg_default_address.clone(),
-    })
+    Json(config_response(&state))
 }
 
 pub async fn update_config(
     State(state): State<AppState>,
+    actor: Actor,
     Json(body): Json<UpdateConfigRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    if let Some(dns) = &body.wg_default_dns {
-        crate::db::settings::set(&state.db, "wg_default_dns", dns)
-            .await
-            .map_err(AppError::Internal)?;
+    let changes: Vec<(&str, String)> = [
+        ("wg_host", body.wg_host),
+        ("wg_port", body.wg_port.map(|v| v.to_string())),
+        ("wg_default_dns", body.wg_default_dns),
+        ("wg_allowed_ips", body.wg_allowed_ips),
+        (
+            "wg_mtu",
+            body.wg_mtu
+                .map(|v| v.map(|v| v.to_string()).unwrap_or_default()),
+        ),
+        (
+            "wg_persistent_keepalive",
+            body.wg_persistent_keepalive.map(|v| v.to_string()),
+        ),
+        (
+            "member_max_clients",
+            body.member_max_clients.map(|v| v.to_string()),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.map(|v| (key, v)))
+    .collect();
+
+    let before = config_response(&state);
+
+    // Validate everything before saving anything
+    let mut settings = state.settings.read().unwrap().clone();
+    for (key, value) in &changes {
+        settings.set(key, value).map_err(AppError::BadRequest)?;
     }
-    if let Some(ips) = &body.wg_allowed_ips {
-        crate::db::settings::set(&state.db, "wg_allowed_ips", ips)
+    for (key, _) in &changes {
+        let value = settings.get(key).unwrap_or_default();
+        crate::db::settings::set(&state.db, key, &value)
             .await
             .map_err(AppError::Internal)?;
     }
-    Ok(Json(serde_json::json!({ "ok": true })))
+    // Apply only our keys to the live settings: a concurrent update may have
+    // changed the others since the copy above
+    {
+        let mut live = state.settings.write().unwrap();
+        for (key, value) in &changes {
+            live.set(key, value).map_err(AppError::BadRequest)?;
+        }
+    }
+
+    let after = config_response(&state);
+    state
+        .audit
+        .record(
+            &actor,
+            "config.update",
+            None,
+            snapshot(&before),
+            snapshot(&after),
+        )
+        .await;
+    Ok(Json(after))
+}
+
+fn config_response(state: &AppState) -> ConfigResponse {
+    let settings = state.settings.read().unwrap().clone();
+    ConfigResponse {
+        wg_host: settings.wg_host,
+        wg_port: settings.wg_port,
+        wg_default_dns: settings.wg_default_dns,
+        wg_allowed_ips: settings.wg_allowed_ips,
+        wg_default_address: state.config.wg_default_address.clone(),
+        wg_mtu: settings.wg_mtu,
+        wg_persistent_keepalive: settings.wg_persistent_keepalive,
+        member_max_clients: settings.member_max_clients,
+    }
 }
@@ -0,0 +1,103 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, Method},
+    middleware::Next,
+    response::Response,
+};
+use std::net::SocketAddr;
+
+use crate::api::client_ip::request_host;
+use crate::api::session::{get_bearer_token, get_session_id_from_headers};
+use crate::{error::AppError, AppState};
+
+/// Middleware that refuses cross-site requests that could change state. Any
+/// method other than GET/HEAD/OPTIONS must come from a page on this server
+/// (or `WG_ALLOWED_ORIGINS`), judged by `Origin`, then `Referer`, then
+/// `Sec-Fetch-Site`. Requests authenticated only by an API token carry no
+/// ambient credentials and are let through, as are non-browser clients that
+/// send none of these headers.
+pub async fn check_origin(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(next.run(req).await);
+    }
+    let headers = req.headers();
+    let cookie_auth = get_session_id_from_headers(headers, &state.config).is_some();
+    if !cookie_auth && get_bearer_token(headers).is_some() {
+        return Ok(next.run(req).await);
+    }
+
+    let host = request_host(addr.ip(), headers, &state.config.trusted_proxies);
+    if !same_origin(headers, host.as_deref(), &state.config.allowed_origins) {
+        tracing::warn!(
+            "Refused cross-site {} {} from origin {:?}",
+            req.method(),
+            req.uri().path(),
+            headers.get(header::ORIGIN)
+        );
+        return Err(AppError::Forbidden);
+    }
+    Ok(next.run(req).await)
+}
+
+fn same_origin(headers: &HeaderMap, host: Option<&str>, allowed: &[String]) -> bool {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Some(origin) = header(header::ORIGIN) {
+        return origin_allowed(origin, host, allowed);
+    }
+    if let Some(referer) = header(header::REFERER) {
+        return origin_allowed(referer, host, allowed);
+    }
+    // Older browsers may send neither on same-origin requests
+    match header(header::HeaderName::from_static("sec-fetch-site")) {
+        Some(site) => matches!(site, "same-origin" | "none"),
+        None => true,
+    }
+}
+
+/// Whether `url` (an `Origin` or `Referer`) is on `host`, ignoring the
+/// scheme since TLS may end at a proxy, or is one of `allowed`.
+fn origin_allowed(url: &str, host: Option<&str>, allowed: &[String]) -> bool {
+    let url = url.to_lowercase();
+    let Some((scheme, rest)) = url.split_once("://") else {
+        // `Origin: null` from sandboxed frames, file:// pages, etc.
+        return false;
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let origin = format!("{scheme}://{authority}");
+    host.is_some_and(|h| authority == h) || allowed.contains(&origin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin_allowed() {
+        let allowed = vec!["https://admin.example.com".to_string()];
+        let host = Some("vpn.example.com");
+        assert!(origin_allowed("https://vpn.example.com", host, &allowed));
+        assert!(origin_allowed(
+            "https://VPN.example.com/clients?x=1",
+            host,
+            &allowed
+        ));
+        assert!(origin_allowed("https://admin.example.com", host, &allowed));
+        assert!(!origin_allowed(
+            "https://vpn.example.com.evil.test",
+            host,
+            &allowed
+        ));
+        assert!(!origin_allowed(
+            "https://vpn.example.com:8443",
+            host,
+            &allowed
+        ));
+        assert!(!origin_allowed("null", host, &allowed));
+        assert!(!origin_allowed("https://vpn.example.com", None, &[]));
+    }
+}
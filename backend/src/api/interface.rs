@@ -1,55 +1,396 @@
-use crate::wireguard::peers;
+use crate::audit::Actor;
+use crate::models::interface::Interface;
+use crate::wireguard::{interface as wgiface, keys, peers};
 use crate::{error::AppError, AppState};
-use axum::{extract::State, response::IntoResponse, Json};
+use anyhow::Context as _;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use serde::Deserialize;
+use wireguard_control::InterfaceName;
+
+#[derive(Deserialize)]
+pub struct CreateInterfaceRequest {
+    pub name: String,
+    pub listen_port: i64,
+    pub ipv4_cidr: String,
+    pub ipv6_cidr: Option<String>,
+    #[serde(default)]
+    pub isolate_clients: bool,
+}
 
 #[derive(Deserialize)]
 pub struct UpdateInterfaceRequest {
     pub listen_port: Option<i64>,
     pub ipv4_cidr: Option<String>,
     pub ipv6_cidr: Option<String>,
+    /// Block traffic between clients, except to exempt ones.
+    pub isolate_clients: Option<bool>,
 }
 
+pub async fn list_interfaces(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let ifaces = crate::db::interfaces::list(&state.db)
+        .await
+        .map_err(AppError::Internal)?;
+    Ok(Json(ifaces.iter().map(public_view).collect::<Vec<_>>()))
+}
+
+/// The default interface, for single-interface setups.
 pub async fn get_interface(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
-    let iface = crate::db::interfaces::get(&state.db)
+    let iface = default_interface(&state).await?;
+    Ok(Json(public_view(&iface)))
+}
+
+pub async fn get_interface_by_id(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let iface = interface_by_id(&state, &id).await?;
+    Ok(Json(public_view(&iface)))
+}
+
+pub async fn create_interface(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(body): Json<CreateInterfaceRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    body.name
+        .parse::<InterfaceName>()
+        .map_err(|e| AppError::BadRequest(format!("Invalid interface name: {e}")))?;
+
+    let (private_key, public_key) = keys::generate_keypair();
+    let iface = Interface {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: body.name,
+        private_key,
+        public_key,
+        listen_port: body.listen_port,
+        ipv4_cidr: body.ipv4_cidr,
+        ipv6_cidr: body.ipv6_cidr,
+        isolate_clients: body.isolate_clients as i64,
+    };
+    validate_interface(&state, &iface).await?;
+
+    crate::db::interfaces::upsert(&state.db, &iface)
         .await
-        .map_err(AppError::Internal)?
-        .ok_or_else(|| AppError::NotFound)?;
-    // Don't expose private key
-    Ok(Json(serde_json::json!({
-        "id": iface.id,
-        "name": iface.name,
-        "public_key": iface.public_key,
-        "listen_port": iface.listen_port,
-        "ipv4_cidr": iface.ipv4_cidr,
-        "ipv6_cidr": iface.ipv6_cidr,
-    })))
+        .map_err(|e| {
+            if crate::db::is_unique_violation(&e) {
+                AppError::Conflict(
+                    "An interface with this name or listen port already exists".to_string(),
+                )
+            } else {
+                AppError::Internal(e)
+            }
+        })?;
+
+    if iface.ipv6_cidr.is_some() {
+        crate::network::check_forwarding(state.config.wg_enable_ip_forward, true);
+    }
+    if let Err(e) = crate::network::bring_up(&state.db, &iface).await {
+        crate::db::interfaces::delete(&state.db, &iface.id)
+            .await
+            .map_err(AppError::Internal)?;
+        return Err(AppError::Internal(e));
+    }
+    refresh_nat_or_warn(&state).await;
+
+    state
+        .audit
+        .record(
+            &actor,
+            "interface.create",
+            Some(&iface.id),
+            None,
+            Some(public_view(&iface)),
+        )
+        .await;
+    Ok((StatusCode::CREATED, Json(public_view(&iface))))
 }
 
+/// Update the default interface, for single-interface setups.
 pub async fn update_interface(
     State(state): State<AppState>,
+    actor: Actor,
     Json(body): Json<UpdateInterfaceRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    let mut iface = crate::db::interfaces::get(&state.db)
+    let iface = default_interface(&state).await?;
+    apply_update(&state, &actor, iface, body).await
+}
+
+pub async fn update_interface_by_id(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(id): Path<String>,
+    Json(body): Json<UpdateInterfaceRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let iface = interface_by_id(&state, &id).await?;
+    apply_update(&state, &actor, iface, body).await
+}
+
+/// Delete an interface that has no clients left, removing its link.
+pub async fn delete_interface(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let iface = interface_by_id(&state, &id).await?;
+
+    let ifaces = crate::db::interfaces::list(&state.db)
         .await
-        .map_err(AppError::Internal)?
-        .ok_or(AppError::NotFound)?;
+        .map_err(AppError::Internal)?;
+    if ifaces.len() == 1 {
+        return Err(AppError::BadRequest(
+            "Cannot delete the last interface".to_string(),
+        ));
+    }
+    let clients = crate::db::clients::list_by_interface(&state.db, &iface.id)
+        .await
+        .map_err(AppError::Internal)?;
+    if !clients.is_empty() {
+        return Err(AppError::Conflict(format!(
+            "{} still has {} client(s); delete or move them first",
+            iface.name,
+            clients.len()
+        )));
+    }
+
+    crate::db::interfaces::delete(&state.db, &iface.id)
+        .await
+        .map_err(AppError::Internal)?;
+    crate::network::tear_down(&iface)
+        .await
+        .unwrap_or_else(|e| tracing::warn!("Failed to delete {}: {}", iface.name, e));
+    refresh_nat_or_warn(&state).await;
 
+    state
+        .audit
+        .record(
+            &actor,
+            "interface.delete",
+            Some(&iface.id),
+            Some(public_view(&iface)),
+            None,
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Apply an interface update. A changed CIDR renumbers the interface's
+/// clients (see `clients::plan_renumber`) and moves the server address, routes
+/// and NAT; the response lists the clients that must re-download their config.
+async fn apply_update(
+    state: &AppState,
+    actor: &Actor,
+    mut iface: Interface,
+    body: UpdateInterfaceRequest,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let old = iface.clone();
     if let Some(port) = body.listen_port {
         iface.listen_port = port;
     }
     if let Some(cidr) = body.ipv4_cidr {
         iface.ipv4_cidr = cidr;
     }
+    let ipv6_added = iface.ipv6_cidr.is_none() && body.ipv6_cidr.is_some();
     iface.ipv6_cidr = body.ipv6_cidr.or(iface.ipv6_cidr);
+    if let Some(isolate) = body.isolate_clients {
+        iface.isolate_clients = isolate as i64;
+    }
+    validate_interface(state, &iface).await?;
 
-    crate::db::interfaces::upsert(&state.db, &iface)
+    let renumbered = crate::api::clients::plan_renumber(&state.db, &old, &iface).await?;
+
+    crate::db::interfaces::update_renumbering(&state.db, &iface, &renumbered)
         .await
-        .map_err(AppError::Internal)?;
+        .map_err(|e| {
+            if crate::db::is_unique_violation(&e) {
+                AppError::Conflict("Another interface already uses this listen port".to_string())
+            } else {
+                AppError::Internal(e)
+            }
+        })?;
+    for client in &renumbered {
+        tracing::info!("Renumbered client {} to {}", client.name, client.ipv4);
+    }
 
     // Re-apply to kernel
     peers::configure_interface(&iface.name, &iface.private_key, iface.listen_port as u16)
         .map_err(AppError::Internal)?;
 
-    Ok(Json(serde_json::json!({ "ok": true })))
+    let old_networks = old.networks().map_err(AppError::Internal)?;
+    let new_networks = iface.networks().map_err(AppError::Internal)?;
+    let mut moved = false;
+    for (from, to) in old_networks.iter().zip(&new_networks) {
+        if from != to {
+            crate::network::move_network(&iface.name, from, to)
+                .await
+                .map_err(AppError::Internal)?;
+            moved = true;
+        }
+    }
+    if moved {
+        crate::api::clients::sync_peers(&state.db, &iface)
+            .await
+            .map_err(AppError::Internal)?;
+    }
+    if ipv6_added {
+        enable_ipv6(state, &iface)
+            .await
+            .map_err(AppError::Internal)?;
+    }
+    if iface.isolate_clients != 0 || old.isolate_clients != 0 {
+        // Isolation follows the interface's networks as well as the setting
+        if moved || ipv6_added || iface.isolate_clients != old.isolate_clients {
+            refresh_nat(state)
+                .await
+                .context("Interface saved, but client isolation could not be applied")?;
+        }
+    } else if moved || ipv6_added {
+        refresh_nat_or_warn(state).await;
+    }
+
+    state
+        .audit
+        .record(
+            actor,
+            "interface.update",
+            Some(&iface.id),
+            Some(public_view(&old)),
+            Some(public_view(&iface)),
+        )
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "ok": true,
+        "renumbered": renumbered
+            .iter()
+            .map(|c| serde_json::json!({
+                "id": c.id,
+                "name": c.name,
+                "ipv4": c.ipv4,
+                "ipv6": c.ipv6,
+            }))
+            .collect::<Vec<_>>(),
+    })))
+}
+
+/// Check ports and CIDRs, and that the interface's networks don't overlap
+/// those of another interface.
+async fn validate_interface(state: &AppState, iface: &Interface) -> Result<(), AppError> {
+    if !(1..=u16::MAX as i64).contains(&iface.listen_port) {
+        return Err(AppError::BadRequest(
+            "listen_port must be between 1 and 65535".to_string(),
+        ));
+    }
+    iface
+        .ipv4_cidr
+        .parse::<Ipv4Net>()
+        .map_err(|_| AppError::BadRequest(format!("Invalid IPv4 CIDR: {}", iface.ipv4_cidr)))?;
+    if let Some(cidr) = &iface.ipv6_cidr {
+        cidr.parse::<Ipv6Net>()
+            .map_err(|_| AppError::BadRequest(format!("Invalid IPv6 CIDR: {cidr}")))?;
+    }
+
+    let networks = iface.networks().map_err(AppError::Internal)?;
+    for other in crate::db::interfaces::list(&state.db)
+        .await
+        .map_err(AppError::Internal)?
+    {
+        if other.id == iface.id {
+            continue;
+        }
+        for theirs in other.networks().map_err(AppError::Internal)? {
+            if let Some(ours) = networks
+                .iter()
+                .find(|ours| crate::api::clients::overlaps(ours, &theirs))
+            {
+                return Err(AppError::Conflict(format!(
+                    "{ours} overlaps the {} network {theirs}",
+                    other.name
+                )));
+            }
+        }
+    }
+    // Subnets routed to site-to-site peers, on this interface or another
+    for client in crate::db::clients::list(&state.db)
+        .await
+        .map_err(AppError::Internal)?
+    {
+        for routed in client.routed_subnets() {
+            if let Some(ours) = networks
+                .iter()
+                .find(|ours| crate::api::clients::overlaps(ours, &routed))
+            {
+                return Err(AppError::Conflict(format!(
+                    "{ours} overlaps {routed} routed to client {}",
+                    client.name
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Bring up IPv6 on a running interface: address existing clients, put the
+/// server address and route on the link and re-sync peers with their `/128`s.
+async fn enable_ipv6(state: &AppState, iface: &Interface) -> anyhow::Result<()> {
+    crate::network::check_forwarding(state.config.wg_enable_ip_forward, true);
+    crate::api::clients::backfill_ipv6(&state.db, iface).await?;
+
+    let Some(cidr) = &iface.ipv6_cidr else {
+        return Ok(());
+    };
+    let network: IpNet = cidr.parse()?;
+    let address = wgiface::server_address(&network)
+        .ok_or_else(|| anyhow::anyhow!("IPv6 CIDR {} has no hosts", cidr))?;
+    let handle = wgiface::connect()?;
+    let idx = wgiface::get_link_index(&handle, &iface.name).await?;
+    wgiface::assign_address(&handle, idx, &address).await?;
+    wgiface::add_route(&handle, idx, &network)
+        .await
+        .unwrap_or_else(|e| tracing::warn!("add_route: {} (may already exist)", e));
+
+    crate::api::clients::sync_peers(&state.db, iface).await
+}
+
+pub(crate) async fn refresh_nat(state: &AppState) -> anyhow::Result<()> {
+    crate::network::refresh_nat(&state.db, &state.uplinks).await
+}
+
+/// Reload the nftables rules where only NAT depends on them.
+async fn refresh_nat_or_warn(state: &AppState) {
+    refresh_nat(state)
+        .await
+        .unwrap_or_else(|e| tracing::warn!("NAT setup failed (may need root): {}", e));
+}
+
+async fn default_interface(state: &AppState) -> Result<Interface, AppError> {
+    crate::db::interfaces::get_default(&state.db)
+        .await
+        .map_err(AppError::Internal)?
+        .ok_or(AppError::NotFound)
+}
+
+async fn interface_by_id(state: &AppState, id: &str) -> Result<Interface, AppError> {
+    crate::db::interfaces::get(&state.db, id)
+        .await
+        .map_err(AppError::Internal)?
+        .ok_or(AppError::NotFound)
+}
+
+// Don't expose private key
+fn public_view(iface: &Interface) -> serde_json::Value {
+    serde_json::json!({
+        "id": iface.id,
+        "name": iface.name,
+        "public_key": iface.public_key,
+        "listen_port": iface.listen_port,
+        "ipv4_cidr": iface.ipv4_cidr,
+        "ipv6_cidr": iface.ipv6_cidr,
+        "isolate_clients": iface.isolate_clients != 0,
+    })
 }
@@ -1,44 +1,134 @@
 use axum::{
+    http::{header, HeaderValue},
     middleware,
     routing::{delete, get, post, put},
     Router,
 };
 use metrics_exporter_prometheus::PrometheusHandle;
+use tower::ServiceBuilder;
 use tower_http::services::{ServeDir, ServeFile};
+use tower_http::set_header::SetResponseHeaderLayer;
 
+use crate::models::user::Role;
 use crate::AppState;
 
+pub mod acl;
+pub mod audit;
 pub mod auth;
+pub mod client_ip;
 pub mod clients;
 pub mod config;
+pub mod csrf;
 pub mod interface;
 pub mod metrics;
+pub mod oidc;
+pub mod portal;
 pub mod session;
 pub mod stats;
+pub mod throttle;
+pub mod tokens;
+pub mod totp;
+pub mod users;
+
+/// Scripts, styles and API calls only from this server; QR codes and
+/// the TOTP enrollment image are `data:` URLs.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'self'; img-src 'self' data:; \
+    style-src 'self' 'unsafe-inline'; object-src 'none'; base-uri 'self'; \
+    form-action 'self'; frame-ancestors 'none'";
 
 pub fn build_router(state: AppState, prom_handle: PrometheusHandle) -> Router {
-    let sessions = state.sessions.clone();
+    // Every logged-in user, members included: their own account and the
+    // self-service portal for the devices they own
+    let member = Router::new()
+        .route("/api/me/totp", post(totp::enroll))
+        .route("/api/me/totp/confirm", post(totp::confirm))
+        .route("/api/me/totp", delete(totp::disable))
+        .route("/api/me/sessions", get(auth::list_own_sessions))
+        .route("/api/me/sessions/{id}", delete(auth::revoke_own_session))
+        .route("/api/me/tokens", get(tokens::list_own))
+        .route("/api/me/tokens", post(tokens::create))
+        .route("/api/me/tokens/{id}", delete(tokens::revoke_own))
+        .route("/api/me/clients", get(portal::list))
+        .route(
+            "/api/me/clients/{id}/configuration",
+            get(portal::download_conf),
+        )
+        .route("/api/me/clients/{id}/qrcode.svg", get(portal::qrcode));
+
+    // Portal changes: members and up, but not viewers or `read` tokens
+    let member_write = Router::new()
+        .route("/api/me/clients", post(portal::create))
+        .route("/api/me/clients/{id}/disable", put(portal::disable))
+        .route_layer(middleware::from_fn(session::require_write));
 
-    let protected = Router::new()
+    // Read-only view of every client, interface and setting
+    let viewer = Router::new()
         .route("/api/client", get(clients::list))
-        .route("/api/client", post(clients::create))
         .route("/api/client/{id}", get(clients::get_one))
+        .route("/api/client/{id}/acl", get(acl::get))
+        .route("/api/interface", get(interface::get_interface))
+        .route("/api/interfaces", get(interface::list_interfaces))
+        .route("/api/interface/{id}", get(interface::get_interface_by_id))
+        .route("/api/stats", get(stats::get_stats))
+        .route("/api/config", get(config::get_config))
+        .route_layer(middleware::from_fn_with_state(
+            Role::Viewer,
+            session::require_role,
+        ));
+
+    // Client management; configs and QR codes carry private keys
+    let operator = Router::new()
+        .route("/api/client", post(clients::create))
         .route("/api/client/{id}", put(clients::update))
         .route("/api/client/{id}", delete(clients::delete))
         .route("/api/client/{id}/enable", put(clients::enable))
         .route("/api/client/{id}/disable", put(clients::disable))
+        .route("/api/client/{id}/acl", put(acl::update))
         .route("/api/client/{id}/qrcode.svg", get(clients::qrcode))
         .route(
             "/api/client/{id}/configuration",
             get(clients::download_conf),
         )
-        .route("/api/interface", get(interface::get_interface))
+        .route(
+            "/api/client/{id}/one-time-link",
+            post(clients::generate_one_time_link),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            Role::Operator,
+            session::require_role,
+        ));
+
+    let admin = Router::new()
         .route("/api/interface", put(interface::update_interface))
-        .route("/api/stats", get(stats::get_stats))
-        .route("/api/config", get(config::get_config))
+        .route("/api/interface", post(interface::create_interface))
+        .route(
+            "/api/interface/{id}",
+            put(interface::update_interface_by_id),
+        )
+        .route("/api/interface/{id}", delete(interface::delete_interface))
         .route("/api/config", put(config::update_config))
+        .route("/api/user", get(users::list))
+        .route("/api/user", post(users::create))
+        .route("/api/user/{id}", get(users::get_one))
+        .route("/api/user/{id}", put(users::update))
+        .route("/api/user/{id}", delete(users::delete))
+        .route("/api/sessions", get(auth::list_all_sessions))
+        .route("/api/sessions/{id}", delete(auth::revoke_session))
+        .route("/api/tokens", get(tokens::list_all))
+        .route("/api/tokens/{id}", delete(tokens::revoke))
+        .route("/api/audit", get(audit::list))
+        .route_layer(middleware::from_fn_with_state(
+            Role::Admin,
+            session::require_role,
+        ));
+
+    let protected = member
+        .merge(member_write)
+        .merge(viewer)
+        .merge(operator)
+        .merge(admin)
         .route_layer(middleware::from_fn_with_state(
-            sessions,
+            state.clone(),
             session::require_auth,
         ));
 
@@ -46,15 +136,238 @@ pub fn build_router(state: AppState, prom_handle: PrometheusHandle) -> Router {
         // Public auth routes
         .route("/api/session", post(auth::login))
         .route("/api/session", get(auth::check))
+        .route("/api/oidc/login", get(oidc::login))
+        .route("/api/oidc/callback", get(oidc::callback))
         .route("/api/session", delete(auth::logout))
+        // One-time config downloads (the token is the credential)
+        .route("/cnf/{token}", get(clients::download_one_time_link))
         // Prometheus metrics (no auth)
         .route("/metrics", get(metrics::prometheus))
         .layer(axum::Extension(prom_handle))
         // Protected routes
         .merge(protected)
-        // React SPA fallback
-        .fallback_service(ServeDir::new(&state.config.static_path).not_found_service(
-            ServeFile::new(format!("{}/index.html", &state.config.static_path)),
+        // Every mutation, including login and logout, must be same-origin
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            csrf::check_origin,
         ))
+        // React SPA fallback
+        .fallback_service(spa(&state.config.static_path))
         .with_state(state)
 }
+
+/// The SPA's files, with headers keeping it out of frames and limiting what
+/// an injected script could load or leak.
+fn spa(static_path: &str) -> Router {
+    let files = ServeDir::new(static_path)
+        .not_found_service(ServeFile::new(format!("{static_path}/index.html")));
+    let headers = ServiceBuilder::new()
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(CONTENT_SECURITY_POLICY),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::X_FRAME_OPTIONS,
+            HeaderValue::from_static("DENY"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::REFERRER_POLICY,
+            HeaderValue::from_static("same-origin"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        ));
+    Router::new().fallback_service(headers.service(files))
+}
+
+/// An `AppState` on a fresh in-memory database, with SSO set up if
+/// `config.oidc` is.
+#[cfg(test)]
+pub(crate) async fn test_state(config: crate::AppConfig) -> AppState {
+    use std::sync::{Arc, RwLock};
+
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+    let db = Arc::new(pool);
+    let settings = crate::models::settings::Settings::from_config(&config).unwrap();
+    let secrets = crate::crypto::SecretBox::new(&[7u8; 32]);
+    AppState {
+        sessions: session::SessionStore::new(db.clone(), &config),
+        login_throttle: throttle::LoginThrottle::new(&config),
+        oidc: config
+            .oidc
+            .clone()
+            .map(|oidc| oidc::Oidc::new(oidc, &secrets).unwrap()),
+        secrets,
+        settings: Arc::new(RwLock::new(settings)),
+        uplinks: Default::default(),
+        audit: crate::audit::AuditLog::new(db.clone(), None).await.unwrap(),
+        config: Arc::new(config),
+        db,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::extract::connect_info::MockConnectInfo;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::models::api_token::{ApiToken, Scope};
+
+    /// A token for `user_id` with `scopes`, as sent in `Authorization`.
+    async fn create_token(state: &AppState, user_id: i64, scopes: &[Scope]) -> String {
+        let token = format!("wgez_{}", uuid::Uuid::new_v4());
+        let api_token = ApiToken {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id,
+            username: "alice".to_string(),
+            name: "script".to_string(),
+            token_hash: session::hash_token(&token),
+            scopes: scopes.to_vec(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            expires_at: None,
+            last_used_at: None,
+        };
+        crate::db::api_tokens::create(&state.db, &api_token)
+            .await
+            .unwrap();
+        token
+    }
+
+    async fn status(app: &Router, method: &str, uri: &str, auth: &str, body: &str) -> StatusCode {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, auth)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.clone().oneshot(req).await.unwrap().status()
+    }
+
+    fn test_app(state: &AppState) -> Router {
+        let handle = metrics_exporter_prometheus::PrometheusBuilder::new()
+            .build_recorder()
+            .handle();
+        build_router(state.clone(), handle).layer(MockConnectInfo(std::net::SocketAddr::from((
+            [127, 0, 0, 1],
+            1234,
+        ))))
+    }
+
+    #[tokio::test]
+    async fn test_token_scopes_through_router() {
+        let state = test_state(crate::AppConfig::for_tests()).await;
+        let app = test_app(&state);
+        let admin = crate::db::users::create(&state.db, "alice", "x", Role::Admin)
+            .await
+            .unwrap();
+        let read = format!(
+            "Bearer {}",
+            create_token(&state, admin, &[Scope::Read]).await
+        );
+        let clients = format!(
+            "Bearer {}",
+            create_token(&state, admin, &[Scope::Clients]).await
+        );
+
+        // Past the scope checks, a missing client is 404
+        for (method, uri) in [
+            ("PUT", "/api/me/clients/missing/disable"),
+            ("PUT", "/api/client/missing/disable"),
+        ] {
+            assert_eq!(
+                status(&app, method, uri, &read, "").await,
+                StatusCode::FORBIDDEN,
+                "read: {method} {uri}"
+            );
+            assert_eq!(
+                status(&app, method, uri, &clients, "").await,
+                StatusCode::NOT_FOUND,
+                "clients: {method} {uri}"
+            );
+        }
+        // A `clients` token of an admin is still no admin
+        assert_eq!(
+            status(&app, "GET", "/api/user", &clients, "").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_only_callers_cannot_change_me_routes() {
+        let state = test_state(crate::AppConfig::for_tests()).await;
+        let app = test_app(&state);
+
+        let alice = crate::db::users::create(&state.db, "alice", "x", Role::Member)
+            .await
+            .unwrap();
+        let read = format!(
+            "Bearer {}",
+            create_token(&state, alice, &[Scope::Read]).await
+        );
+        let other = create_token(&state, alice, &[Scope::Clients]).await;
+        let other_id = crate::db::api_tokens::find_by_hash(&state.db, &session::hash_token(&other))
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+        let (_, browser) = state
+            .sessions
+            .create("alice", Role::Member, None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            status(&app, "GET", "/api/me/clients", &read, "").await,
+            StatusCode::OK
+        );
+        for (method, uri, body) in [
+            ("POST", "/api/me/totp".to_string(), ""),
+            ("POST", "/api/me/clients".to_string(), r#"{"name":"phone"}"#),
+            ("PUT", "/api/me/clients/any/disable".to_string(), ""),
+            ("DELETE", format!("/api/me/sessions/{}", browser.id), ""),
+            ("DELETE", format!("/api/me/tokens/{other_id}"), ""),
+        ] {
+            assert_eq!(
+                status(&app, method, &uri, &read, body).await,
+                StatusCode::FORBIDDEN,
+                "{method} {uri}"
+            );
+        }
+        // Nothing was revoked
+        assert!(crate::db::sessions::get(&state.db, &browser.id)
+            .await
+            .unwrap()
+            .is_some());
+        assert!(crate::db::api_tokens::get(&state.db, &other_id)
+            .await
+            .unwrap()
+            .is_some());
+
+        // Viewers are read-only in the portal too
+        let (viewer, _) = state
+            .sessions
+            .create("victor", Role::Viewer, None, None)
+            .await
+            .unwrap();
+        let req = Request::builder()
+            .method("POST")
+            .uri("/api/me/clients")
+            .header(
+                header::COOKIE,
+                format!("{}={viewer}", session::SESSION_COOKIE),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"name":"phone"}"#))
+            .unwrap();
+        assert_eq!(
+            app.clone().oneshot(req).await.unwrap().status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+
+use crate::api::clients::{self, CreateClientRequest};
+use crate::api::session::{current_user, Session};
+use crate::audit::{snapshot, Actor};
+use crate::models::client::Client;
+use crate::{error::AppError, AppState};
+
+#[derive(Deserialize)]
+pub struct CreateDeviceRequest {
+    pub name: String,
+    /// Public key of a device that generated its own keypair.
+    pub public_key: Option<String>,
+}
+
+/// The caller's own devices.
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = current_user(&state, &session).await?;
+    Ok(Json(
+        crate::db::clients::list_by_owner(&state.db, user.id).await?,
+    ))
+}
+
+/// Add a device on the default interface, up to `member_max_clients`.
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    actor: Actor,
+    Json(body): Json<CreateDeviceRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = current_user(&state, &session).await?;
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("name is required".to_string()));
+    }
+    let max = state.settings.read().unwrap().member_max_clients;
+
+    let client = clients::create_client(
+        &state,
+        CreateClientRequest {
+            name: name.to_string(),
+            public_key: body.public_key,
+            interface_id: None,
+            owner_user_id: Some(user.id),
+        },
+        Some(max),
+    )
+    .await?;
+    state
+        .audit
+        .record(
+            &actor,
+            "client.create",
+            Some(&client.id),
+            None,
+            snapshot(&client),
+        )
+        .await;
+    Ok((StatusCode::CREATED, Json(client)))
+}
+
+pub async fn download_conf(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let client = active_client(&state, &session, &id).await?;
+    let conf = clients::build_client_conf(&state, &client.id).await?;
+    Ok(clients::conf_attachment(conf))
+}
+
+pub async fn qrcode(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let client = active_client(&state, &session, &id).await?;
+    let conf = clients::build_client_conf(&state, &client.id).await?;
+    clients::qrcode_svg(&conf)
+}
+
+/// Switch off a lost device. Only an operator can turn it back on.
+pub async fn disable(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    actor: Actor,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut client = owned_client(&state, &session, &id).await?;
+    let before = snapshot(&client);
+    clients::disable_client(&state, &client).await?;
+    client.enabled = 0;
+    state
+        .audit
+        .record(
+            &actor,
+            "client.disable",
+            Some(&id),
+            before,
+            snapshot(&client),
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Other users' clients are reported as not found.
+async fn owned_client(state: &AppState, session: &Session, id: &str) -> Result<Client, AppError> {
+    let user = current_user(state, session).await?;
+    crate::db::clients::get_owned(&state.db, id, user.id)
+        .await?
+        .ok_or(AppError::NotFound)
+}
+
+/// An owned client whose config may be handed out: like one-time links, not
+/// once it's disabled or expired.
+async fn active_client(state: &AppState, session: &Session, id: &str) -> Result<Client, AppError> {
+    let client = owned_client(state, session, id).await?;
+    if client.enabled == 0 || client.expired {
+        return Err(AppError::NotFound);
+    }
+    Ok(client)
+}
@@ -1,29 +1,174 @@
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-
 use axum::{
     extract::{Request, State},
     http::{header, HeaderMap, StatusCode},
     middleware::Next,
     response::Response,
 };
-use cookie::Cookie;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Duration, Utc};
+use cookie::{Cookie, SameSite};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::db::Db;
+use crate::error::AppError;
+use crate::models::api_token::ApiToken;
+use crate::models::user::{Role, User};
+use crate::{AppConfig, AppState};
+
+pub use crate::models::session::Session;
 
 pub const SESSION_COOKIE: &str = "wg_session";
+/// `SESSION_COOKIE` with the `__Host-` prefix, which browsers only accept
+/// when `Secure`, on path `/` and without a `Domain`.
+pub const HOST_SESSION_COOKIE: &str = "__Host-wg_session";
 
-/// In-memory session store: session_id → username
-pub type SessionStore = Arc<RwLock<HashMap<String, String>>>;
+/// Don't write `last_seen_at` on every request; this is the resolution of the
+/// idle timeout.
+pub(crate) const TOUCH_INTERVAL: Duration = Duration::seconds(60);
 
-pub fn new_store() -> SessionStore {
-    Arc::new(RwLock::new(HashMap::new()))
+/// Sessions stored in SQLite, keyed by the hash of the cookie token. They end
+/// after `idle_timeout` without requests, or `max_age` after login.
+#[derive(Clone)]
+pub struct SessionStore {
+    db: Db,
+    idle_timeout: Duration,
+    max_age: Duration,
+}
+
+impl SessionStore {
+    pub fn new(db: Db, config: &AppConfig) -> Self {
+        Self {
+            db,
+            idle_timeout: Duration::seconds(config.session_idle_timeout as i64),
+            max_age: Duration::seconds(config.session_max_age as i64),
+        }
+    }
+
+    /// Start a session, returning the cookie token and the stored session.
+    pub async fn create(
+        &self,
+        username: &str,
+        role: Role,
+        ip: Option<String>,
+        user_agent: Option<String>,
+    ) -> anyhow::Result<(String, Session)> {
+        let now = Utc::now();
+        crate::db::sessions::delete_expired(&self.db, &now.to_rfc3339()).await?;
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        let session = Session {
+            id: hash_token(&token),
+            username: username.to_string(),
+            role,
+            created_at: now.to_rfc3339(),
+            last_seen_at: now.to_rfc3339(),
+            expires_at: self.expiry(now, now).to_rfc3339(),
+            ip,
+            user_agent,
+        };
+        crate::db::sessions::create(&self.db, &session).await?;
+        Ok((token, session))
+    }
+
+    /// Look up a live session by cookie token, extending its idle timeout.
+    /// Expired sessions are deleted.
+    pub async fn get(&self, token: &str) -> anyhow::Result<Option<Session>> {
+        let Some(mut session) = crate::db::sessions::get(&self.db, &hash_token(token)).await?
+        else {
+            return Ok(None);
+        };
+        let now = Utc::now();
+        if parse_time(&session.expires_at) <= now {
+            crate::db::sessions::delete(&self.db, &session.id).await?;
+            return Ok(None);
+        }
+        if now - parse_time(&session.last_seen_at) >= TOUCH_INTERVAL {
+            let created = parse_time(&session.created_at);
+            session.last_seen_at = now.to_rfc3339();
+            session.expires_at = self.expiry(created, now).to_rfc3339();
+            crate::db::sessions::touch(
+                &self.db,
+                &session.id,
+                &session.last_seen_at,
+                &session.expires_at,
+            )
+            .await?;
+        }
+        Ok(Some(session))
+    }
+
+    pub async fn revoke(&self, id: &str) -> anyhow::Result<()> {
+        crate::db::sessions::delete(&self.db, id).await
+    }
+
+    pub async fn revoke_token(&self, token: &str) -> anyhow::Result<()> {
+        self.revoke(&hash_token(token)).await
+    }
+
+    /// Drop every session of `username` except `keep`, e.g. after its
+    /// password changes or the account is disabled.
+    pub async fn revoke_user(&self, username: &str, keep: Option<&str>) -> anyhow::Result<()> {
+        crate::db::sessions::delete_for_user(&self.db, username, keep).await
+    }
+
+    /// Apply a role change to the user's existing sessions.
+    pub async fn set_user_role(&self, username: &str, role: Role) -> anyhow::Result<()> {
+        crate::db::sessions::set_role_for_user(&self.db, username, role).await
+    }
+
+    fn expiry(&self, created: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+        (now + self.idle_timeout).min(created + self.max_age)
+    }
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Timestamps are written by this server; an unparsable one counts as expired.
+pub(crate) fn parse_time(ts: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(ts)
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+pub fn session_cookie_name(config: &AppConfig) -> &'static str {
+    if config.cookie_host_prefix {
+        HOST_SESSION_COOKIE
+    } else {
+        SESSION_COOKIE
+    }
+}
+
+/// The session cookie carrying `token`, or clearing it when `max_age` is
+/// zero. `https` is whether the client connected over HTTPS, for
+/// `WG_COOKIE_SECURE=auto`.
+pub(crate) fn session_cookie(
+    config: &AppConfig,
+    token: String,
+    max_age: Duration,
+    https: bool,
+) -> Cookie<'static> {
+    let secure = config.cookie_host_prefix || config.cookie_secure.unwrap_or(https);
+    Cookie::build((session_cookie_name(config), token))
+        .http_only(true)
+        .secure(secure)
+        .same_site(SameSite::Strict)
+        .path("/")
+        .max_age(cookie::time::Duration::seconds(max_age.num_seconds()))
+        .build()
 }
 
 /// Extract session_id from headers.
-pub fn get_session_id_from_headers(headers: &HeaderMap) -> Option<String> {
+pub fn get_session_id_from_headers(headers: &HeaderMap, config: &AppConfig) -> Option<String> {
+    let name = session_cookie_name(config);
     let val = headers.get(header::COOKIE)?.to_str().ok()?;
     for part in val.split(';') {
         if let Ok(cookie) = Cookie::parse(part.trim().to_owned()) {
-            if cookie.name() == SESSION_COOKIE {
+            if cookie.name() == name {
                 return Some(cookie.value().to_string());
             }
         }
@@ -31,16 +176,80 @@ pub fn get_session_id_from_headers(headers: &HeaderMap) -> Option<String> {
     None
 }
 
-/// Middleware that rejects unauthenticated requests with 401.
+/// The account behind a session.
+pub(crate) async fn current_user(state: &AppState, session: &Session) -> Result<User, AppError> {
+    crate::db::users::find_by_username(&state.db, &session.username)
+        .await
+        .map_err(AppError::Internal)?
+        .ok_or(AppError::NotFound)
+}
+
+/// Extract an `Authorization: Bearer` token from headers.
+pub fn get_bearer_token(headers: &HeaderMap) -> Option<String> {
+    let val = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = val.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim().to_string())
+}
+
+/// Middleware that rejects requests without a live session cookie or a valid
+/// API token with 401. Token requests also carry the `ApiToken`.
 pub async fn require_auth(
-    State(sessions): State<SessionStore>,
-    req: Request,
+    State(state): State<AppState>,
+    mut req: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    if let Some(session_id) = get_session_id_from_headers(req.headers()) {
-        if sessions.read().unwrap().contains_key(&session_id) {
+    let internal = |e: anyhow::Error| {
+        tracing::error!("Session lookup failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    if let Some(token) = get_session_id_from_headers(req.headers(), &state.config) {
+        if let Some(session) = state.sessions.get(&token).await.map_err(internal)? {
+            req.extensions_mut().insert(session);
+            return Ok(next.run(req).await);
+        }
+    }
+    if let Some(bearer) = get_bearer_token(req.headers()) {
+        let found = crate::api::tokens::authenticate(&state, &bearer)
+            .await
+            .map_err(internal)?;
+        if let Some((session, token)) = found {
+            req.extensions_mut().insert(session);
+            req.extensions_mut().insert(token);
             return Ok(next.run(req).await);
         }
     }
     Err(StatusCode::UNAUTHORIZED)
 }
+
+/// Middleware that rejects sessions whose role is below `min` with 403. Runs
+/// inside `require_auth`.
+pub async fn require_role(
+    State(min): State<Role>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    match req.extensions().get::<Session>() {
+        Some(session) if session.role >= min => Ok(next.run(req).await),
+        Some(_) => Err(AppError::Forbidden),
+        None => Err(AppError::Unauthorized),
+    }
+}
+
+/// Middleware that rejects read-only callers with 403: viewers, and API
+/// tokens with only the `read` scope. For changes members may make, since
+/// the member role ranks below viewer. Runs inside `require_auth`.
+pub async fn require_write(req: Request, next: Next) -> Result<Response, AppError> {
+    let Some(session) = req.extensions().get::<Session>() else {
+        return Err(AppError::Unauthorized);
+    };
+    let read_only_token = req
+        .extensions()
+        .get::<ApiToken>()
+        .is_some_and(|t| t.max_role() <= Role::Viewer);
+    if session.role == Role::Viewer || read_only_token {
+        return Err(AppError::Forbidden);
+    }
+    Ok(next.run(req).await)
+}
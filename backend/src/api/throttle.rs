@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::AppConfig;
+
+/// Failures allowed before backoff starts.
+const FREE_ATTEMPTS: u32 = 3;
+/// Failure records are forgotten this long after the last failure (or the
+/// lockout, if longer).
+const FORGET_AFTER: Duration = Duration::from_secs(3600);
+/// Prune forgotten records once the table grows past this.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Ip(IpAddr),
+    User(String),
+}
+
+struct Entry {
+    failures: u32,
+    last_failure: Instant,
+    blocked_until: Instant,
+}
+
+/// In-memory login failure tracking per client IP and per username. After
+/// `FREE_ATTEMPTS` failures each further attempt waits twice as long as the
+/// last, and `max_attempts` failures lock the key out for `lockout`.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    entries: Arc<Mutex<HashMap<Key, Entry>>>,
+    max_attempts: u32,
+    lockout: Duration,
+}
+
+impl LoginThrottle {
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            entries: Arc::default(),
+            max_attempts: config.login_max_attempts,
+            lockout: Duration::from_secs(config.login_lockout),
+        }
+    }
+
+    /// How long the caller must wait before trying this IP and username
+    /// again, if at all.
+    pub fn retry_after(&self, ip: IpAddr, username: &str) -> Option<Duration> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        keys(ip, username)
+            .iter()
+            .filter_map(|key| entries.get(key))
+            .map(|entry| entry.blocked_until.saturating_duration_since(now))
+            .filter(|wait| !wait.is_zero())
+            .max()
+    }
+
+    /// Count a failed login. Returns true if this failure locked out the IP
+    /// or the username.
+    pub fn record_failure(&self, ip: IpAddr, username: &str) -> bool {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() > PRUNE_THRESHOLD {
+            let forget = FORGET_AFTER.max(self.lockout);
+            entries.retain(|_, e| now.duration_since(e.last_failure) < forget);
+        }
+
+        let mut locked = false;
+        for key in keys(ip, username) {
+            let entry = entries.entry(key).or_insert(Entry {
+                failures: 0,
+                last_failure: now,
+                blocked_until: now,
+            });
+            if now.duration_since(entry.last_failure) >= FORGET_AFTER.max(self.lockout) {
+                entry.failures = 0;
+            }
+            entry.failures += 1;
+            entry.last_failure = now;
+            entry.blocked_until = now + backoff(entry.failures, self.max_attempts, self.lockout);
+            locked |= entry.failures == self.max_attempts;
+        }
+        locked
+    }
+
+    /// A successful login clears the username's failures. The IP's failures
+    /// stay, so one valid account doesn't reset a credential-stuffing run.
+    pub fn record_success(&self, username: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .remove(&Key::User(username.to_lowercase()));
+    }
+}
+
+fn keys(ip: IpAddr, username: &str) -> [Key; 2] {
+    [Key::Ip(ip), Key::User(username.to_lowercase())]
+}
+
+/// Wait imposed after the given number of consecutive failures: none for the
+/// first few, then 1s, 2s, 4s, ... and the full lockout from `max_attempts`.
+fn backoff(failures: u32, max_attempts: u32, lockout: Duration) -> Duration {
+    if failures >= max_attempts {
+        lockout
+    } else if failures <= FREE_ATTEMPTS {
+        Duration::ZERO
+    } else {
+        let exp = (failures - FREE_ATTEMPTS - 1).min(31);
+        Duration::from_secs(1u64 << exp).min(lockout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let lockout = Duration::from_secs(900);
+        assert_eq!(backoff(1, 10, lockout), Duration::ZERO);
+        assert_eq!(backoff(3, 10, lockout), Duration::ZERO);
+        assert_eq!(backoff(4, 10, lockout), Duration::from_secs(1));
+        assert_eq!(backoff(6, 10, lockout), Duration::from_secs(4));
+        assert_eq!(backoff(10, 10, lockout), lockout);
+        assert_eq!(
+            backoff(8, 100, Duration::from_secs(5)),
+            Duration::from_secs(5)
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::api::session::{current_user, hash_token, parse_time, Session, TOUCH_INTERVAL};
+use crate::audit::{snapshot, Actor};
+use crate::models::api_token::{ApiToken, Scope};
+use crate::{error::AppError, AppState};
+
+/// Prefix that makes leaked tokens easy to spot in logs and secret scanners.
+const TOKEN_PREFIX: &str = "wgez_";
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// RFC 3339; omit for a token that doesn't expire.
+    pub expires_at: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CreateTokenResponse {
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+    /// The bearer token itself; it is never shown again.
+    pub token: String,
+}
+
+/// Resolve a bearer token to the session-like identity handlers see. The
+/// role is the lower of the owner's role and the token's scopes, so demoting
+/// or disabling a user also limits their tokens.
+pub async fn authenticate(
+    state: &AppState,
+    bearer: &str,
+) -> anyhow::Result<Option<(Session, ApiToken)>> {
+    let Some(mut token) =
+        crate::db::api_tokens::find_by_hash(&state.db, &hash_token(bearer)).await?
+    else {
+        return Ok(None);
+    };
+    let now = Utc::now();
+    if token
+        .expires_at
+        .as_deref()
+        .is_some_and(|ts| parse_time(ts) <= now)
+    {
+        return Ok(None);
+    }
+    let Some(user) = crate::db::users::get(&state.db, token.user_id)
+        .await?
+        .filter(|u| u.enabled != 0)
+    else {
+        return Ok(None);
+    };
+
+    let stale = token
+        .last_used_at
+        .as_deref()
+        .is_none_or(|ts| now - parse_time(ts) >= TOUCH_INTERVAL);
+    if stale {
+        token.last_used_at = Some(now.to_rfc3339());
+        crate::db::api_tokens::touch(&state.db, &token.id, &now.to_rfc3339()).await?;
+    }
+
+    let session = Session {
+        id: format!("token:{}", token.id),
+        username: user.username,
+        role: token.role_for(user.role),
+        created_at: token.created_at.clone(),
+        last_seen_at: now.to_rfc3339(),
+        expires_at: token.expires_at.clone().unwrap_or_default(),
+        ip: None,
+        user_agent: None,
+    };
+    Ok(Some((session, token)))
+}
+
+/// The caller's own tokens.
+pub async fn list_own(
+    State(state): State<AppState>,
+    Extension(current): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = current_user_id(&state, &current).await?;
+    Ok(Json(
+        crate::db::api_tokens::list_for_user(&state.db, user).await?,
+    ))
+}
+
+/// Create a token for the caller. Tokens can't mint more tokens; this needs
+/// a login session.
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(current): Extension<Session>,
+    auth_token: Option<Extension<ApiToken>>,
+    actor: Actor,
+    Json(body): Json<CreateTokenRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    if auth_token.is_some() {
+        return Err(AppError::Forbidden);
+    }
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("name is required".to_string()));
+    }
+    if body.scopes.is_empty() {
+        return Err(AppError::BadRequest(
+            "At least one scope is required".to_string(),
+        ));
+    }
+    if let Some(scope) = body.scopes.iter().find(|s| s.role() > current.role) {
+        return Err(AppError::BadRequest(format!(
+            "Scope {} exceeds your role",
+            scope.as_str()
+        )));
+    }
+    if let Some(ts) = &body.expires_at {
+        let expires = DateTime::parse_from_rfc3339(ts).map_err(|_| {
+            AppError::BadRequest("expires_at must be an RFC 3339 timestamp".to_string())
+        })?;
+        if expires <= Utc::now() {
+            return Err(AppError::BadRequest(
+                "expires_at must be in the future".to_string(),
+            ));
+        }
+    }
+
+    let mut scopes = body.scopes;
+    scopes.sort_by_key(|s| s.role());
+    scopes.dedup();
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = format!("{TOKEN_PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes));
+    let api_token = ApiToken {
+        id: uuid::Uuid::new_v4().to_string(),
+        user_id: current_user_id(&state, &current).await?,
+        username: current.username.clone(),
+        name: name.to_string(),
+        token_hash: hash_token(&token),
+        scopes,
+        created_at: Utc::now().to_rfc3339(),
+        expires_at: body.expires_at,
+        last_used_at: None,
+    };
+    crate::db::api_tokens::create(&state.db, &api_token).await?;
+    state
+        .audit
+        .record(
+            &actor,
+            "token.create",
+            Some(&api_token.id),
+            None,
+            snapshot(&api_token),
+        )
+        .await;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateTokenResponse { api_token, token }),
+    ))
+}
+
+/// Revoke one of the caller's tokens. Like `create`, this needs a login
+/// session.
+pub async fn revoke_own(
+    State(state): State<AppState>,
+    Extension(current): Extension<Session>,
+    auth_token: Option<Extension<ApiToken>>,
+    actor: Actor,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    if auth_token.is_some() {
+        return Err(AppError::Forbidden);
+    }
+    let user = current_user_id(&state, &current).await?;
+    let token = crate::db::api_tokens::get(&state.db, &id)
+        .await?
+        .filter(|t| t.user_id == user)
+        .ok_or(AppError::NotFound)?;
+    crate::db::api_tokens::delete(&state.db, &token.id).await?;
+    state
+        .audit
+        .record(
+            &actor,
+            "token.revoke",
+            Some(&token.id),
+            snapshot(&token),
+            None,
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Every user's tokens (admin only).
+pub async fn list_all(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(crate::db::api_tokens::list(&state.db).await?))
+}
+
+pub async fn revoke(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = crate::db::api_tokens::get(&state.db, &id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    crate::db::api_tokens::delete(&state.db, &token.id).await?;
+    state
+        .audit
+        .record(
+            &actor,
+            "token.revoke",
+            Some(&token.id),
+            snapshot(&token),
+            None,
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn current_user_id(state: &AppState, session: &Session) -> Result<i64, AppError> {
+    Ok(current_user(state, session).await?.id)
+}
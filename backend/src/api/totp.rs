@@ -0,0 +1,328 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::api::session::{current_user, Session};
+use crate::audit::{snapshot, Actor};
+use crate::models::api_token::ApiToken;
+use crate::models::user::User;
+use crate::{error::AppError, AppState};
+
+const ISSUER: &str = "wg-easy";
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Lowercase letters and digits without look-alikes (0/o, 1/l/i).
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+#[derive(Deserialize)]
+pub struct CodeRequest {
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct EnrollResponse {
+    /// Base32 secret for manual entry.
+    pub secret: String,
+    pub otpauth_url: String,
+    /// Provisioning QR code as a base64 PNG.
+    pub qr_png_base64: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Start TOTP enrollment for the current user. The new secret stays pending,
+/// and login doesn't ask for codes, until `confirm` verifies one. Like all
+/// TOTP changes, this needs a login session, not an API token.
+pub async fn enroll(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    auth_token: Option<Extension<ApiToken>>,
+) -> Result<impl IntoResponse, AppError> {
+    if auth_token.is_some() {
+        return Err(AppError::Forbidden);
+    }
+    let user = current_user(&state, &session).await?;
+    if user.totp_secret.is_some() {
+        return Err(AppError::Conflict(
+            "TOTP is already enabled; disable it first".to_string(),
+        ));
+    }
+
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let secret = Secret::Raw(bytes.to_vec()).to_encoded().to_string();
+    let totp = build_totp(&secret, &user.username)?;
+    let qr_png_base64 = totp
+        .get_qr_base64()
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("QR error: {}", e)))?;
+
+    crate::db::users::set_totp_pending_secret(
+        &state.db,
+        user.id,
+        Some(&state.secrets.encrypt(&secret)?),
+    )
+    .await
+    .map_err(AppError::Internal)?;
+
+    Ok(Json(EnrollResponse {
+        otpauth_url: totp.get_url(),
+        secret,
+        qr_png_base64,
+    }))
+}
+
+/// Activate the pending secret once the user proves their app generates
+/// matching codes. Returns the recovery codes; they are never shown again.
+pub async fn confirm(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    auth_token: Option<Extension<ApiToken>>,
+    actor: Actor,
+    Json(body): Json<CodeRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    if auth_token.is_some() {
+        return Err(AppError::Forbidden);
+    }
+    let user = current_user(&state, &session).await?;
+    let sealed = user
+        .totp_pending_secret
+        .as_deref()
+        .ok_or_else(|| AppError::BadRequest("No TOTP enrollment in progress".to_string()))?;
+    let secret = state.secrets.decrypt(sealed)?;
+    let Some(step) = matching_step(&build_totp(&secret, &user.username)?, &body.code)? else {
+        return Err(AppError::BadRequest("Invalid TOTP code".to_string()));
+    };
+
+    let codes = generate_recovery_codes();
+    let hashes: Vec<String> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+    crate::db::users::set_totp_secret(&state.db, user.id, Some(sealed))
+        .await
+        .map_err(AppError::Internal)?;
+    crate::db::users::set_totp_pending_secret(&state.db, user.id, None)
+        .await
+        .map_err(AppError::Internal)?;
+    // The confirming code can't then be used to log in
+    crate::db::users::claim_totp_step(&state.db, user.id, step as i64)
+        .await
+        .map_err(AppError::Internal)?;
+    crate::db::users::replace_recovery_codes(&state.db, user.id, &hashes)
+        .await
+        .map_err(AppError::Internal)?;
+    record_change(&state, &actor, &user, "totp.enable").await?;
+
+    Ok(Json(RecoveryCodesResponse {
+        recovery_codes: codes,
+    }))
+}
+
+/// Turn TOTP off for the current user; requires a current code or a
+/// recovery code.
+pub async fn disable(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    auth_token: Option<Extension<ApiToken>>,
+    actor: Actor,
+    Json(body): Json<CodeRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    if auth_token.is_some() {
+        return Err(AppError::Forbidden);
+    }
+    let user = current_user(&state, &session).await?;
+    if user.totp_secret.is_none() {
+        return Err(AppError::BadRequest("TOTP is not enabled".to_string()));
+    }
+    if !verify_second_factor(&state, &actor, &user, &body.code).await? {
+        return Err(AppError::BadRequest("Invalid code".to_string()));
+    }
+
+    crate::db::users::set_totp_secret(&state.db, user.id, None)
+        .await
+        .map_err(AppError::Internal)?;
+    crate::db::users::replace_recovery_codes(&state.db, user.id, &[])
+        .await
+        .map_err(AppError::Internal)?;
+    record_change(&state, &actor, &user, "totp.disable").await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Check a login's second factor: a code from the user's authenticator app
+/// for a later time step than the last one accepted, or an unused recovery
+/// code (which is then used up and audited).
+pub async fn verify_second_factor(
+    state: &AppState,
+    actor: &Actor,
+    user: &User,
+    code: &str,
+) -> Result<bool, AppError> {
+    let Some(stored) = &user.totp_secret else {
+        return Ok(true);
+    };
+    // Secrets written before enrollment existed were raw bytes, not sealed base32
+    let totp = if stored.starts_with("v1:") {
+        build_totp(&state.secrets.decrypt(stored)?, &user.username)?
+    } else {
+        TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            stored.as_bytes().to_vec(),
+            None,
+            user.username.clone(),
+        )
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("TOTP error: {}", e)))?
+    };
+    if let Some(step) = matching_step(&totp, code)? {
+        return crate::db::users::claim_totp_step(&state.db, user.id, step as i64)
+            .await
+            .map_err(AppError::Internal);
+    }
+    let used =
+        crate::db::users::consume_recovery_code(&state.db, user.id, &hash_recovery_code(code))
+            .await
+            .map_err(AppError::Internal)?;
+    if used {
+        state
+            .audit
+            .record(
+                actor,
+                "totp.recovery_code_used",
+                Some(&user.id.to_string()),
+                None,
+                None,
+            )
+            .await;
+    }
+    Ok(used)
+}
+
+/// Audit a change to the user's TOTP setup, with the account before and after.
+async fn record_change(
+    state: &AppState,
+    actor: &Actor,
+    before: &User,
+    action: &str,
+) -> Result<(), AppError> {
+    let after = crate::db::users::get(&state.db, before.id)
+        .await
+        .map_err(AppError::Internal)?;
+    state
+        .audit
+        .record(
+            actor,
+            action,
+            Some(&before.id.to_string()),
+            snapshot(before),
+            after.as_ref().and_then(snapshot),
+        )
+        .await;
+    Ok(())
+}
+
+fn build_totp(secret: &str, username: &str) -> Result<TOTP, AppError> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("Invalid TOTP secret: {:?}", e)))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        bytes,
+        Some(ISSUER.to_string()),
+        username.to_string(),
+    )
+    .map_err(|e| AppError::BadRequest(format!("Cannot create TOTP for this account: {e}")))
+}
+
+/// The time step `code` is valid for, allowing the TOTP's skew either side
+/// of now.
+fn matching_step(totp: &TOTP, code: &str) -> Result<Option<u64>, AppError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("TOTP check: {}", e)))?
+        .as_secs();
+    Ok(step_at(totp, code.trim(), now))
+}
+
+fn step_at(totp: &TOTP, code: &str, now: u64) -> Option<u64> {
+    let mut exact = totp.clone();
+    exact.skew = 0;
+    let current = now / totp.step;
+    let skew = u64::from(totp.skew);
+    (current.saturating_sub(skew)..=current + skew).find(|step| exact.check(code, step * totp.step))
+}
+
+/// Codes look like `k7mq2-xh4tp`.
+fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = (0..10)
+                .map(|_| {
+                    RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char
+                })
+                .collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+/// SHA-256 of a recovery code, ignoring case, spaces and dashes.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_code_hash_is_normalized() {
+        assert_eq!(
+            hash_recovery_code("k7mq2-xh4tp"),
+            hash_recovery_code(" K7MQ2 XH4TP ")
+        );
+        assert_ne!(
+            hash_recovery_code("k7mq2-xh4tp"),
+            hash_recovery_code("k7mq2-xh4tq")
+        );
+    }
+
+    #[test]
+    fn test_step_at() {
+        let totp = build_totp("JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP", "alice").unwrap();
+        let now = 1_700_000_000;
+        let current = now / 30;
+        assert_eq!(step_at(&totp, &totp.generate(now), now), Some(current));
+        assert_eq!(
+            step_at(&totp, &totp.generate(now - 30), now),
+            Some(current - 1)
+        );
+        assert_eq!(
+            step_at(&totp, &totp.generate(now + 30), now),
+            Some(current + 1)
+        );
+        assert_eq!(step_at(&totp, &totp.generate(now - 60), now), None);
+    }
+
+    #[test]
+    fn test_generated_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes
+            .iter()
+            .all(|c| c.len() == 11 && c.as_bytes()[5] == b'-'));
+    }
+}
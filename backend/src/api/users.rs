@@ -0,0 +1,241 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+
+use crate::api::session::Session;
+use crate::audit::{snapshot, Actor};
+use crate::models::user::{Role, User};
+use crate::{error::AppError, AppState};
+
+const MIN_PASSWORD_LEN: usize = 8;
+
+#[derive(Deserialize)]
+pub struct CreateUserRequest {
+    pub username: String,
+    pub password: String,
+    /// Defaults to `viewer`.
+    pub role: Option<Role>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateUserRequest {
+    pub password: Option<String>,
+    pub enabled: Option<bool>,
+    pub role: Option<Role>,
+    /// Link the account to this `sub` at the configured OIDC provider so SSO
+    /// can log into it; `null` unlinks.
+    #[serde(default, deserialize_with = "crate::api::clients::nullable")]
+    pub oidc_subject: Option<Option<String>>,
+}
+
+pub async fn list(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let users = crate::db::users::list(&state.db)
+        .await
+        .map_err(AppError::Internal)?;
+    Ok(Json(users))
+}
+
+pub async fn create(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(body): Json<CreateUserRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let username = body.username.trim();
+    if username.is_empty() {
+        return Err(AppError::BadRequest("Username is required".to_string()));
+    }
+    let hash = hash_password(&body.password)?;
+    let id = crate::db::users::create(
+        &state.db,
+        username,
+        &hash,
+        body.role.unwrap_or(Role::Viewer),
+    )
+    .await
+    .map_err(|e| {
+        if crate::db::is_unique_violation(&e) {
+            AppError::Conflict(format!("User {username} already exists"))
+        } else {
+            AppError::Internal(e)
+        }
+    })?;
+    let user = get_user(&state, id).await?;
+    state
+        .audit
+        .record(
+            &actor,
+            "user.create",
+            Some(&id.to_string()),
+            None,
+            snapshot(&user),
+        )
+        .await;
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+pub async fn get_one(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(get_user(&state, id).await?))
+}
+
+/// Change a user's password, role or SSO link, or enable/disable the account. A new
+/// password or disabling ends the user's other sessions (a user changing their
+/// own password stays logged in); a new role applies to existing sessions.
+pub async fn update(
+    State(state): State<AppState>,
+    Extension(current): Extension<Session>,
+    actor: Actor,
+    Path(id): Path<i64>,
+    Json(body): Json<UpdateUserRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = get_user(&state, id).await?;
+
+    let demoted = body.role.is_some_and(|role| role != Role::Admin);
+    if (body.enabled == Some(false) || demoted) && user.enabled != 0 && user.role == Role::Admin {
+        ensure_other_admin(&state, &user).await?;
+    }
+    let hash = body.password.as_deref().map(hash_password).transpose()?;
+    let issuer = state.config.oidc.as_ref().map(|oidc| oidc.issuer.as_str());
+    let oidc_subject = body
+        .oidc_subject
+        .as_ref()
+        .map(|subject| subject.as_deref().map(str::trim));
+    if let Some(Some(subject)) = oidc_subject {
+        if issuer.is_none() {
+            return Err(AppError::BadRequest("SSO is not configured".to_string()));
+        }
+        if subject.is_empty() {
+            return Err(AppError::BadRequest("OIDC subject is empty".to_string()));
+        }
+    }
+
+    // First, as the one write that can conflict
+    if let Some(subject) = oidc_subject {
+        let identity = subject
+            .zip(issuer)
+            .map(|(subject, issuer)| (issuer, subject));
+        crate::db::users::set_oidc(&state.db, id, identity)
+            .await
+            .map_err(|e| {
+                if crate::db::is_unique_violation(&e) {
+                    AppError::Conflict("Another user is linked to that SSO identity".to_string())
+                } else {
+                    AppError::Internal(e)
+                }
+            })?;
+    }
+
+    if let Some(hash) = &hash {
+        crate::db::users::update_password(&state.db, id, hash)
+            .await
+            .map_err(AppError::Internal)?;
+    }
+    if let Some(enabled) = body.enabled {
+        crate::db::users::set_enabled(&state.db, id, enabled)
+            .await
+            .map_err(AppError::Internal)?;
+    }
+    if let Some(role) = body.role {
+        crate::db::users::set_role(&state.db, id, role)
+            .await
+            .map_err(AppError::Internal)?;
+        state.sessions.set_user_role(&user.username, role).await?;
+    }
+
+    if body.enabled == Some(false) {
+        state.sessions.revoke_user(&user.username, None).await?;
+    } else if hash.is_some() {
+        state
+            .sessions
+            .revoke_user(&user.username, Some(&current.id))
+            .await?;
+    }
+
+    let updated = get_user(&state, id).await?;
+    let mut after = snapshot(&updated);
+    if let (Some(after), true) = (
+        after.as_mut().and_then(|v| v.as_object_mut()),
+        hash.is_some(),
+    ) {
+        after.insert("password_changed".to_string(), true.into());
+    }
+    state
+        .audit
+        .record(
+            &actor,
+            "user.update",
+            Some(&id.to_string()),
+            snapshot(&user),
+            after,
+        )
+        .await;
+    Ok(Json(updated))
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = get_user(&state, id).await?;
+    if user.enabled != 0 && user.role == Role::Admin {
+        ensure_other_admin(&state, &user).await?;
+    }
+
+    crate::db::users::delete(&state.db, id)
+        .await
+        .map_err(AppError::Internal)?;
+    state.sessions.revoke_user(&user.username, None).await?;
+
+    state
+        .audit
+        .record(
+            &actor,
+            "user.delete",
+            Some(&id.to_string()),
+            snapshot(&user),
+            None,
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Refuse to disable, demote or delete the last enabled admin, which would
+/// leave nobody able to manage users.
+async fn ensure_other_admin(state: &AppState, user: &User) -> Result<(), AppError> {
+    let others = crate::db::users::list(&state.db)
+        .await
+        .map_err(AppError::Internal)?
+        .into_iter()
+        .any(|u| u.id != user.id && u.enabled != 0 && u.role == Role::Admin);
+    if others {
+        Ok(())
+    } else {
+        Err(AppError::BadRequest(
+            "Cannot remove the last enabled admin".to_string(),
+        ))
+    }
+}
+
+async fn get_user(state: &AppState, id: i64) -> Result<User, AppError> {
+    crate::db::users::get(&state.db, id)
+        .await
+        .map_err(AppError::Internal)?
+        .ok_or(AppError::NotFound)
+}
+
+fn hash_password(password: &str) -> Result<String, AppError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(AppError::BadRequest(format!(
+            "Password must be at least {MIN_PASSWORD_LEN} characters"
+        )));
+    }
+    bcrypt::hash(password, bcrypt::DEFAULT_COST)
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("bcrypt error: {}", e)))
+}
@@ -0,0 +1,152 @@
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::db::Db;
+use crate::models::audit::AuditEntry;
+
+/// Fields never written to the audit log, at any depth of a snapshot.
+const REDACTED_FIELDS: &[&str] = &[
+    "private_key",
+    "preshared_key",
+    "one_time_link",
+    "download_url",
+    "password",
+    "password_hash",
+    "token",
+    "token_hash",
+    "totp_secret",
+    "totp_pending_secret",
+];
+
+/// Who is acting and from where. Handlers get it from the request; the
+/// server's own actions use `Actor::system()`.
+#[derive(Debug, Clone, Default)]
+pub struct Actor {
+    pub username: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl Actor {
+    pub fn system() -> Self {
+        Self::default()
+    }
+
+    /// The same request, attributed to `username`, e.g. a login attempt.
+    pub fn as_user(&self, username: &str) -> Self {
+        Self {
+            username: Some(username.to_string()),
+            ip: self.ip.clone(),
+        }
+    }
+}
+
+/// Records changes in the `audit_log` table and, if configured, appends
+/// them as JSON lines to a file for SIEM ingestion. Failing to record is
+/// logged but doesn't fail the change, which has already happened.
+#[derive(Clone)]
+pub struct AuditLog {
+    db: Db,
+    file: Option<Arc<Mutex<tokio::fs::File>>>,
+}
+
+impl AuditLog {
+    pub async fn new(db: Db, file_path: Option<&str>) -> anyhow::Result<Self> {
+        let file = match file_path {
+            Some(path) => {
+                let file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Cannot open audit log {path}: {e}"))?;
+                Some(Arc::new(Mutex::new(file)))
+            }
+            None => None,
+        };
+        Ok(Self { db, file })
+    }
+
+    /// Record an action. `before`/`after` are snapshots of the target, with
+    /// secrets stripped here.
+    pub async fn record(
+        &self,
+        actor: &Actor,
+        action: &str,
+        target_id: Option<&str>,
+        before: Option<Value>,
+        after: Option<Value>,
+    ) {
+        let mut entry = AuditEntry {
+            id: 0,
+            created_at: timestamp(Utc::now()),
+            actor: actor.username.clone(),
+            action: action.to_string(),
+            target_id: target_id.map(str::to_string),
+            before: before.map(redact),
+            after: after.map(redact),
+            ip: actor.ip.clone(),
+        };
+        match crate::db::audit::insert(&self.db, &entry).await {
+            Ok(id) => entry.id = id,
+            Err(e) => tracing::error!("Failed to write audit entry {action}: {e:#}"),
+        }
+
+        if let Some(file) = &self.file {
+            let mut line = serde_json::to_string(&entry).unwrap_or_default();
+            line.push('\n');
+            if let Err(e) = file.lock().await.write_all(line.as_bytes()).await {
+                tracing::error!("Failed to append to audit log file: {e}");
+            }
+        }
+    }
+}
+
+/// A JSON snapshot of a record for `before`/`after`.
+pub fn snapshot<T: Serialize>(value: &T) -> Option<Value> {
+    serde_json::to_value(value).ok()
+}
+
+/// Audit timestamps sort correctly as strings; filters use the same format.
+pub fn timestamp(time: chrono::DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn redact(mut value: Value) -> Value {
+    match &mut value {
+        Value::Object(map) => {
+            map.retain(|key, _| !REDACTED_FIELDS.contains(&key.as_str()));
+            for v in map.values_mut() {
+                *v = redact(v.take());
+            }
+        }
+        Value::Array(items) => {
+            for v in items.iter_mut() {
+                *v = redact(v.take());
+            }
+        }
+        _ => {}
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_nested_secrets() {
+        let value = serde_json::json!({
+            "name": "phone",
+            "preshared_key": "secret",
+            "renumbered": [{ "id": "a", "private_key": "secret" }],
+        });
+        assert_eq!(
+            redact(value),
+            serde_json::json!({ "name": "phone", "renumbered": [{ "id": "a" }] })
+        );
+    }
+}
@@ -1,4 +1,8 @@
 use anyhow::{bail, Context};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::models::user::Role;
 use tracing::warn;
 
 #[derive(Debug, Clone)]
@@ -10,6 +14,12 @@ pub struct AppConfig {
     pub wg_default_address: String,
     pub wg_default_dns: String,
     pub wg_allowed_ips: String,
+    pub wg_persistent_keepalive: u16,
+    pub member_max_clients: u32,
+    /// `None` follows the interfaces of the default routes.
+    pub wg_outbound_iface: Option<String>,
+    /// Turn on the kernel's IP forwarding at startup if it's off.
+    pub wg_enable_ip_forward: bool,
     pub wg_pre_up: Option<String>,
     pub wg_post_up: Option<String>,
     pub wg_pre_down: Option<String>,
@@ -18,9 +28,118 @@ pub struct AppConfig {
     pub port: u16,
     pub insecure: bool,
     pub password_hash: Option<String>,
+    pub session_idle_timeout: u64,
+    pub session_max_age: u64,
+    pub login_max_attempts: u32,
+    pub login_lockout: u64,
+    pub trusted_proxies: Vec<IpNet>,
+    /// `Secure` flag on the session cookie; `None` sets it when a trusted
+    /// proxy reports the request came over HTTPS.
+    pub cookie_secure: Option<bool>,
+    /// Name the session cookie `__Host-wg_session`, pinning it to this host.
+    /// Implies `Secure`.
+    pub cookie_host_prefix: bool,
+    /// Origins besides the server's own that may send cookie-authenticated
+    /// mutations, e.g. `https://vpn.example.com`.
+    pub allowed_origins: Vec<String>,
+    pub oidc: Option<OidcConfig>,
     // Paths
     pub db_path: String,
     pub static_path: String,
+    /// Also append audit entries here as JSON lines.
+    pub audit_log_file: Option<String>,
+    // Secrets at rest
+    pub encryption_key: Option<String>,
+    pub encryption_key_file: String,
+}
+
+/// OpenID Connect single sign-on, enabled by setting `OIDC_ISSUER`.
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// Must point at `/api/oidc/callback` and be registered with the provider.
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+    /// Dotted path into the ID token, e.g. `preferred_username`.
+    pub username_claim: String,
+    /// Dotted path to the user's groups, e.g. `groups` or `realm_access.roles`.
+    pub groups_claim: String,
+    /// If non-empty, users must be in one of these groups.
+    pub allowed_groups: Vec<String>,
+    /// Group → role; a user gets the highest role of their groups.
+    pub role_mapping: Vec<(String, Role)>,
+    /// Role for allowed users no mapping matches; `None` refuses them.
+    pub default_role: Option<Role>,
+    /// Create a user row on first login instead of requiring one.
+    pub auto_provision: bool,
+}
+
+impl OidcConfig {
+    fn from_env() -> anyhow::Result<Option<Self>> {
+        let Some(issuer) = std::env::var("OIDC_ISSUER").ok().filter(|s| !s.is_empty()) else {
+            return Ok(None);
+        };
+        let client_id = std::env::var("OIDC_CLIENT_ID")
+            .context("OIDC_CLIENT_ID is required with OIDC_ISSUER")?;
+        let client_secret = std::env::var("OIDC_CLIENT_SECRET")
+            .ok()
+            .filter(|s| !s.is_empty());
+        let redirect_url = std::env::var("OIDC_REDIRECT_URL")
+            .context("OIDC_REDIRECT_URL is required with OIDC_ISSUER")?;
+
+        let list = |name: &str, default: &str, separators: &[char]| -> Vec<String> {
+            std::env::var(name)
+                .unwrap_or_else(|_| default.to_string())
+                .split(separators)
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        // Scopes may be space-separated as in OAuth; group names may contain spaces
+        let scopes = list("OIDC_SCOPES", "openid,profile,email", &[',', ' ']);
+        let allowed_groups = list("OIDC_ALLOWED_GROUPS", "", &[',']);
+        let role_mapping = list("OIDC_ROLE_MAPPING", "", &[','])
+            .iter()
+            .map(|entry| {
+                let (group, role) = entry.rsplit_once('=').with_context(|| {
+                    format!("OIDC_ROLE_MAPPING: expected group=role, got {entry}")
+                })?;
+                let role = role
+                    .parse::<Role>()
+                    .map_err(|e| anyhow::anyhow!("OIDC_ROLE_MAPPING: {e}"))?;
+                Ok((group.to_string(), role))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let default_role = std::env::var("OIDC_DEFAULT_ROLE")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<Role>())
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("OIDC_DEFAULT_ROLE: {e}"))?;
+        let auto_provision = std::env::var("OIDC_AUTO_PROVISION")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase()
+            == "true";
+
+        Ok(Some(Self {
+            issuer,
+            client_id,
+            client_secret,
+            redirect_url,
+            scopes,
+            username_claim: std::env::var("OIDC_USERNAME_CLAIM")
+                .unwrap_or_else(|_| "preferred_username".to_string()),
+            groups_claim: std::env::var("OIDC_GROUPS_CLAIM")
+                .unwrap_or_else(|_| "groups".to_string()),
+            allowed_groups,
+            role_mapping,
+            default_role,
+            auto_provision,
+        }))
+    }
 }
 
 impl AppConfig {
@@ -50,6 +169,24 @@ impl AppConfig {
         let wg_allowed_ips =
             std::env::var("WG_ALLOWED_IPS").unwrap_or_else(|_| "0.0.0.0/0".to_string());
 
+        let wg_persistent_keepalive: u16 = std::env::var("WG_PERSISTENT_KEEPALIVE")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .context("WG_PERSISTENT_KEEPALIVE must be a number of seconds")?;
+
+        let member_max_clients: u32 = std::env::var("WG_MEMBER_MAX_CLIENTS")
+            .unwrap_or_else(|_| "3".to_string())
+            .parse()
+            .context("WG_MEMBER_MAX_CLIENTS must be a number")?;
+
+        let wg_outbound_iface = std::env::var("WG_OUTBOUND_IFACE")
+            .ok()
+            .filter(|s| !s.is_empty());
+        let wg_enable_ip_forward = std::env::var("WG_ENABLE_IP_FORWARD")
+            .unwrap_or_else(|_| "true".to_string())
+            .to_lowercase()
+            == "true";
+
         // Shell hooks are not supported in scratch image — log warning and ignore
         let wg_pre_up = std::env::var("WG_PRE_UP").ok();
         let wg_post_up = std::env::var("WG_POST_UP").ok();
@@ -80,11 +217,68 @@ impl AppConfig {
             .to_lowercase()
             == "true";
 
+        // Only used to create the first admin account
         let password_hash = std::env::var("PASSWORD_HASH").ok();
 
-        if !insecure && password_hash.is_none() {
-            bail!("PASSWORD_HASH is required when INSECURE=false (set a bcrypt hash or use INSECURE=true for development only)");
+        let session_idle_timeout: u64 = std::env::var("WG_SESSION_IDLE_TIMEOUT")
+            .unwrap_or_else(|_| "86400".to_string())
+            .parse()
+            .context("WG_SESSION_IDLE_TIMEOUT must be a number of seconds")?;
+        let session_max_age: u64 = std::env::var("WG_SESSION_MAX_AGE")
+            .unwrap_or_else(|_| "2592000".to_string())
+            .parse()
+            .context("WG_SESSION_MAX_AGE must be a number of seconds")?;
+
+        let login_max_attempts: u32 = std::env::var("WG_LOGIN_MAX_ATTEMPTS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()
+            .context("WG_LOGIN_MAX_ATTEMPTS must be a number")?;
+        if login_max_attempts == 0 {
+            bail!("WG_LOGIN_MAX_ATTEMPTS must be at least 1");
         }
+        let login_lockout: u64 = std::env::var("WG_LOGIN_LOCKOUT")
+            .unwrap_or_else(|_| "900".to_string())
+            .parse()
+            .context("WG_LOGIN_LOCKOUT must be a number of seconds")?;
+
+        // Reverse proxies whose X-Forwarded-For / X-Real-IP headers are believed
+        let trusted_proxies = std::env::var("WG_TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<IpNet>()
+                    .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                    .with_context(|| format!("WG_TRUSTED_PROXIES: invalid IP or CIDR {s}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let cookie_secure = match std::env::var("WG_COOKIE_SECURE")
+            .unwrap_or_else(|_| "auto".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "auto" | "" => None,
+            "true" => Some(true),
+            "false" => Some(false),
+            other => bail!("WG_COOKIE_SECURE must be auto, true or false, got {other}"),
+        };
+        let cookie_host_prefix = std::env::var("WG_COOKIE_HOST_PREFIX")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase()
+            == "true";
+        if cookie_host_prefix && cookie_secure == Some(false) {
+            bail!("WG_COOKIE_HOST_PREFIX requires a Secure cookie; unset WG_COOKIE_SECURE=false");
+        }
+        let allowed_origins = std::env::var("WG_ALLOWED_ORIGINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().trim_end_matches('/').to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let oidc = OidcConfig::from_env()?;
 
         let db_path =
             std::env::var("WG_DB_PATH").unwrap_or_else(|_| "/etc/wireguard/wg-easy.db".to_string());
@@ -92,6 +286,14 @@ impl AppConfig {
         let static_path =
             std::env::var("WG_STATIC_PATH").unwrap_or_else(|_| "/app/static".to_string());
 
+        let audit_log_file = std::env::var("WG_AUDIT_LOG_FILE")
+            .ok()
+            .filter(|s| !s.is_empty());
+
+        let encryption_key = std::env::var("WG_ENCRYPTION_KEY").ok();
+        let encryption_key_file = std::env::var("WG_ENCRYPTION_KEY_FILE")
+            .unwrap_or_else(|_| "/etc/wireguard/wg-easy.key".to_string());
+
         Ok(Self {
             wg_host,
             wg_port,
@@ -99,6 +301,10 @@ impl AppConfig {
             wg_default_address,
             wg_default_dns,
             wg_allowed_ips,
+            wg_persistent_keepalive,
+            member_max_clients,
+            wg_outbound_iface,
+            wg_enable_ip_forward,
             wg_pre_up,
             wg_post_up,
             wg_pre_down,
@@ -106,8 +312,61 @@ impl AppConfig {
             port,
             insecure,
             password_hash,
+            session_idle_timeout,
+            session_max_age,
+            login_max_attempts,
+            login_lockout,
+            trusted_proxies,
+            cookie_secure,
+            cookie_host_prefix,
+            allowed_origins,
+            oidc,
             db_path,
             static_path,
+            audit_log_file,
+            encryption_key,
+            encryption_key_file,
         })
     }
 }
+
+#[cfg(test)]
+impl AppConfig {
+    /// The defaults `from_env` gives an empty environment, for tests that
+    /// must not depend on the shell's.
+    pub(crate) fn for_tests() -> Self {
+        Self {
+            wg_host: "vpn.example.com".to_string(),
+            wg_port: 51820,
+            wg_mtu: None,
+            wg_default_address: "10.8.0.x".to_string(),
+            wg_default_dns: "1.1.1.1".to_string(),
+            wg_allowed_ips: "0.0.0.0/0".to_string(),
+            wg_persistent_keepalive: 0,
+            member_max_clients: 3,
+            wg_outbound_iface: None,
+            wg_enable_ip_forward: false,
+            wg_pre_up: None,
+            wg_post_up: None,
+            wg_pre_down: None,
+            wg_post_down: None,
+            port: 51821,
+            insecure: false,
+            password_hash: None,
+            session_idle_timeout: 86400,
+            session_max_age: 2592000,
+            login_max_attempts: 10,
+            login_lockout: 900,
+            trusted_proxies: Vec::new(),
+            cookie_secure: None,
+            cookie_host_prefix: false,
+            allowed_origins: Vec::new(),
+            oidc: None,
+            db_path: ":memory:".to_string(),
+            static_path: "static".to_string(),
+            audit_log_file: None,
+            encryption_key: None,
+            encryption_key_file: String::new(),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::{
+    aead::{Aead, OsRng},
+    AeadCore, ChaCha20Poly1305, Key, KeyInit, Nonce,
+};
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use std::io::Write;
+use std::path::Path;
+use tracing::info;
+
+use crate::AppConfig;
+
+/// Version prefix of sealed values, so the format can evolve later.
+const SEALED_PREFIX: &str = "v1:";
+const NONCE_LEN: usize = 12;
+
+/// Encrypts secrets (client private keys) before they are written to SQLite.
+///
+/// Sealed values are `v1:` followed by base64 of `nonce || ciphertext`.
+#[derive(Clone)]
+pub struct SecretBox {
+    cipher: ChaCha20Poly1305,
+    key: [u8; 32],
+}
+
+impl SecretBox {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+            key: *key,
+        }
+    }
+
+    /// A box keyed by HKDF-SHA256 of this one's key with `label`, for values
+    /// that must not share the key, e.g. ones handed to browsers.
+    pub fn derive(&self, label: &str) -> Self {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &self.key)
+            .expand(label.as_bytes(), &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self::new(&key)
+    }
+
+    /// Load the key from `WG_ENCRYPTION_KEY`, or from `WG_ENCRYPTION_KEY_FILE`,
+    /// generating the file on first start.
+    pub fn from_config(config: &AppConfig) -> anyhow::Result<Self> {
+        if let Some(key_b64) = &config.encryption_key {
+            let key = decode_key(key_b64).context("WG_ENCRYPTION_KEY is invalid")?;
+            return Ok(Self::new(&key));
+        }
+
+        let path = Path::new(&config.encryption_key_file);
+        if !path.exists() {
+            let mut key = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+            write_key_file(path, &STANDARD.encode(key))?;
+            info!("Generated encryption key at {}", path.display());
+        }
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read encryption key {}", path.display()))?;
+        let key = decode_key(contents.trim())
+            .with_context(|| format!("Encryption key {} is invalid", path.display()))?;
+        Ok(Self::new(&key))
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> anyhow::Result<String> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| anyhow!("Encryption failed"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{SEALED_PREFIX}{}", STANDARD.encode(sealed)))
+    }
+
+    pub fn decrypt(&self, sealed: &str) -> anyhow::Result<String> {
+        let encoded = sealed
+            .strip_prefix(SEALED_PREFIX)
+            .ok_or_else(|| anyhow!("Unknown sealed value format"))?;
+        let bytes = STANDARD.decode(encoded).context("Invalid sealed value")?;
+        if bytes.len() <= NONCE_LEN {
+            bail!("Sealed value is truncated");
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Decryption failed (wrong encryption key?)"))?;
+        String::from_utf8(plaintext).context("Decrypted value is not UTF-8")
+    }
+}
+
+fn decode_key(key_b64: &str) -> anyhow::Result<[u8; 32]> {
+    let bytes = STANDARD.decode(key_b64).context("key must be base64")?;
+    bytes
+        .try_into()
+        .map_err(|_| anyhow!("key must be 32 bytes"))
+}
+
+fn write_key_file(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let mut opts = std::fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o600);
+    }
+    let mut file = opts
+        .open(path)
+        .with_context(|| format!("Failed to create encryption key {}", path.display()))?;
+    writeln!(file, "{contents}")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let sb = SecretBox::new(&[7u8; 32]);
+        let sealed = sb.encrypt("secret").unwrap();
+        assert!(sealed.starts_with(SEALED_PREFIX));
+        assert_eq!(sb.decrypt(&sealed).unwrap(), "secret");
+    }
+
+    #[test]
+    fn test_nonce_is_random() {
+        let sb = SecretBox::new(&[7u8; 32]);
+        assert_ne!(sb.encrypt("secret").unwrap(), sb.encrypt("secret").unwrap());
+    }
+
+    #[test]
+    fn test_derived_key_differs() {
+        let sb = SecretBox::new(&[7u8; 32]);
+        let derived = sb.derive("oidc-state");
+        let sealed = derived.encrypt("secret").unwrap();
+        assert!(sb.decrypt(&sealed).is_err());
+        assert_eq!(
+            SecretBox::new(&[7u8; 32])
+                .derive("oidc-state")
+                .decrypt(&sealed)
+                .unwrap(),
+            "secret"
+        );
+        assert!(sb.derive("other").decrypt(&sealed).is_err());
+    }
+
+    #[test]
+    fn test_wrong_key_fails() {
+        let sealed = SecretBox::new(&[1u8; 32]).encrypt("secret").unwrap();
+        assert!(SecretBox::new(&[2u8; 32]).decrypt(&sealed).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::models::acl::{AclRule, PortRange};
+use anyhow::anyhow;
+use sqlx::{Pool, Row, Sqlite};
+
+// Unknown values (from a newer version) fail the load rather than being
+// dropped, which could widen what a client may reach
+fn row_to_rule(r: &sqlx::sqlite::SqliteRow) -> anyhow::Result<AclRule> {
+    let ports = match (
+        r.get::<Option<i64>, _>("port_from"),
+        r.get::<Option<i64>, _>("port_to"),
+    ) {
+        (Some(from), Some(to)) => Some(PortRange {
+            from: u16::try_from(from)?,
+            to: u16::try_from(to)?,
+        }),
+        _ => None,
+    };
+    Ok(AclRule {
+        action: r
+            .get::<String, _>("action")
+            .parse()
+            .map_err(|e| anyhow!("{e}"))?,
+        cidr: r.get("cidr"),
+        protocol: r
+            .get::<Option<String>, _>("protocol")
+            .map(|p| p.parse())
+            .transpose()
+            .map_err(|e| anyhow!("{e}"))?,
+        ports,
+    })
+}
+
+/// A client's ACL, in order.
+pub async fn list(pool: &Pool<Sqlite>, client_id: &str) -> anyhow::Result<Vec<AclRule>> {
+    let rows = sqlx::query(
+        "SELECT action, cidr, protocol, port_from, port_to FROM client_acl_rules WHERE client_id = ? ORDER BY position",
+    )
+    .bind(client_id)
+    .fetch_all(pool)
+    .await?;
+    rows.iter().map(row_to_rule).collect()
+}
+
+/// Every client's ACL rules as `(client_id, rule)`, each client's in order.
+pub async fn list_all(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<(String, AclRule)>> {
+    let rows = sqlx::query(
+        "SELECT client_id, action, cidr, protocol, port_from, port_to FROM client_acl_rules ORDER BY client_id, position",
+    )
+    .fetch_all(pool)
+    .await?;
+    rows.iter()
+        .map(|r| Ok((r.get("client_id"), row_to_rule(r)?)))
+        .collect()
+}
+
+/// Replace a client's ACL (an empty slice removes it).
+pub async fn replace(
+    pool: &Pool<Sqlite>,
+    client_id: &str,
+    rules: &[AclRule],
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM client_acl_rules WHERE client_id = ?")
+        .bind(client_id)
+        .execute(&mut *tx)
+        .await?;
+    for (position, rule) in rules.iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO client_acl_rules (client_id, position, action, cidr, protocol, port_from, port_to) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(client_id)
+        .bind(position as i64)
+        .bind(rule.action.as_str())
+        .bind(&rule.cidr)
+        .bind(rule.protocol.map(|p| p.as_str()))
+        .bind(rule.ports.map(|p| i64::from(p.from)))
+        .bind(rule.ports.map(|p| i64::from(p.to)))
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use crate::models::api_token::ApiToken;
+use sqlx::{Pool, Row, Sqlite};
+
+const SELECT_ALL: &str = "SELECT t.id, t.user_id, u.username, t.name, t.token_hash, t.scopes, t.created_at, t.expires_at, t.last_used_at FROM api_tokens t JOIN users u ON u.id = t.user_id";
+
+fn row_to_token(r: &sqlx::sqlite::SqliteRow) -> ApiToken {
+    ApiToken {
+        id: r.get("id"),
+        user_id: r.get("user_id"),
+        username: r.get("username"),
+        name: r.get("name"),
+        token_hash: r.get("token_hash"),
+        // Unknown scopes (from a newer version) are dropped, not widened
+        scopes: r
+            .get::<String, _>("scopes")
+            .split(',')
+            .filter_map(|s| s.parse().ok())
+            .collect(),
+        created_at: r.get("created_at"),
+        expires_at: r.get("expires_at"),
+        last_used_at: r.get("last_used_at"),
+    }
+}
+
+pub async fn list(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<ApiToken>> {
+    let rows = sqlx::query(&format!("{SELECT_ALL} ORDER BY t.created_at"))
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(row_to_token).collect())
+}
+
+pub async fn list_for_user(pool: &Pool<Sqlite>, user_id: i64) -> anyhow::Result<Vec<ApiToken>> {
+    let rows = sqlx::query(&format!(
+        "{SELECT_ALL} WHERE t.user_id = ? ORDER BY t.created_at"
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(row_to_token).collect())
+}
+
+pub async fn get(pool: &Pool<Sqlite>, id: &str) -> anyhow::Result<Option<ApiToken>> {
+    let row = sqlx::query(&format!("{SELECT_ALL} WHERE t.id = ?"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(row_to_token))
+}
+
+pub async fn find_by_hash(pool: &Pool<Sqlite>, hash: &str) -> anyhow::Result<Option<ApiToken>> {
+    let row = sqlx::query(&format!("{SELECT_ALL} WHERE t.token_hash = ?"))
+        .bind(hash)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(row_to_token))
+}
+
+pub async fn create(pool: &Pool<Sqlite>, token: &ApiToken) -> anyhow::Result<()> {
+    let scopes: Vec<&str> = token.scopes.iter().map(|s| s.as_str()).collect();
+    sqlx::query(
+        "INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at, expires_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&token.id)
+    .bind(token.user_id)
+    .bind(&token.name)
+    .bind(&token.token_hash)
+    .bind(scopes.join(","))
+    .bind(&token.created_at)
+    .bind(&token.expires_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn touch(pool: &Pool<Sqlite>, id: &str, last_used_at: &str) -> anyhow::Result<()> {
+    sqlx::query("UPDATE api_tokens SET last_used_at = ? WHERE id = ?")
+        .bind(last_used_at)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete(pool: &Pool<Sqlite>, id: &str) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM api_tokens WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use crate::models::audit::AuditEntry;
+use sqlx::{Pool, QueryBuilder, Row, Sqlite};
+
+fn row_to_entry(r: &sqlx::sqlite::SqliteRow) -> AuditEntry {
+    let json = |col: &str| {
+        r.get::<Option<String>, _>(col)
+            .and_then(|s| serde_json::from_str(&s).ok())
+    };
+    AuditEntry {
+        id: r.get("id"),
+        created_at: r.get("created_at"),
+        actor: r.get("actor"),
+        action: r.get("action"),
+        target_id: r.get("target_id"),
+        before: json("before"),
+        after: json("after"),
+        ip: r.get("ip"),
+    }
+}
+
+/// Filters for `list`; unset fields match everything.
+#[derive(Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    /// An exact action, or a prefix such as `client` for `client.*`.
+    pub action: Option<String>,
+    pub target_id: Option<String>,
+    /// RFC 3339 bounds on `created_at`, inclusive.
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+/// Stores the entry and returns its id.
+pub async fn insert(pool: &Pool<Sqlite>, entry: &AuditEntry) -> anyhow::Result<i64> {
+    let result = sqlx::query(
+        "INSERT INTO audit_log (created_at, actor, action, target_id, before, after, ip) VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&entry.created_at)
+    .bind(&entry.actor)
+    .bind(&entry.action)
+    .bind(&entry.target_id)
+    .bind(entry.before.as_ref().map(|v| v.to_string()))
+    .bind(entry.after.as_ref().map(|v| v.to_string()))
+    .bind(&entry.ip)
+    .execute(pool)
+    .await?;
+    Ok(result.last_insert_rowid())
+}
+
+/// Matching entries, newest first, and the total number of matches.
+pub async fn list(
+    pool: &Pool<Sqlite>,
+    filter: &AuditFilter,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<(Vec<AuditEntry>, i64)> {
+    let mut count = QueryBuilder::new("SELECT COUNT(*) AS n FROM audit_log");
+    push_filter(&mut count, filter);
+    let total: i64 = count.build().fetch_one(pool).await?.get("n");
+
+    let mut query = QueryBuilder::new(
+        "SELECT id, created_at, actor, action, target_id, before, after, ip FROM audit_log",
+    );
+    push_filter(&mut query, filter);
+    query
+        .push(" ORDER BY id DESC LIMIT ")
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+    let rows = query.build().fetch_all(pool).await?;
+    Ok((rows.iter().map(row_to_entry).collect(), total))
+}
+
+fn push_filter<'a>(query: &mut QueryBuilder<'a, Sqlite>, filter: &'a AuditFilter) {
+    query.push(" WHERE 1 = 1");
+    if let Some(actor) = &filter.actor {
+        query.push(" AND actor = ").push_bind(actor);
+    }
+    if let Some(action) = &filter.action {
+        query
+            .push(" AND (action = ")
+            .push_bind(action)
+            .push(" OR action LIKE ")
+            .push_bind(format!("{}.%", escape_like(action)))
+            .push(" ESCAPE '\\')");
+    }
+    if let Some(target_id) = &filter.target_id {
+        query.push(" AND target_id = ").push_bind(target_id);
+    }
+    if let Some(since) = &filter.since {
+        query.push(" AND created_at >= ").push_bind(since);
+    }
+    if let Some(until) = &filter.until {
+        query.push(" AND created_at <= ").push_bind(until);
+    }
+}
+
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
@@ -1,23 +1,35 @@
-use crate::models::client::Client;
+use crate::models::client::{expiry_passed, Client};
 use sqlx::{Pool, Row, Sqlite};
 
 fn row_to_client(r: &sqlx::sqlite::SqliteRow) -> Client {
     Client {
         id: r.get("id"),
+        interface_id: r.get("interface_id"),
         name: r.get("name"),
         public_key: r.get("public_key"),
         preshared_key: r.get("preshared_key"),
+        private_key: r.get("private_key"),
         ipv4: r.get("ipv4"),
         ipv6: r.get("ipv6"),
         enabled: r.get("enabled"),
         created_at: r.get("created_at"),
         expires_at: r.get("expires_at"),
-        download_url: r.get("download_url"),
+        expiry_action: r.get("expiry_action"),
+        expired: expiry_passed(r.get("expires_at"), chrono::Utc::now()),
         one_time_link: r.get("one_time_link"),
+        one_time_link_expires_at: r.get("one_time_link_expires_at"),
+        dns: r.get("dns"),
+        allowed_ips: r.get("allowed_ips"),
+        mtu: r.get("mtu"),
+        persistent_keepalive: r.get("persistent_keepalive"),
+        no_preshared_key: r.get("no_preshared_key"),
+        server_allowed_ips: r.get("server_allowed_ips"),
+        owner_user_id: r.get("owner_user_id"),
+        isolation_exempt: r.get("isolation_exempt"),
     }
 }
 
-const SELECT_ALL: &str = "SELECT id, name, public_key, preshared_key, ipv4, ipv6, enabled, created_at, expires_at, download_url, one_time_link FROM clients";
+const SELECT_ALL: &str = "SELECT id, interface_id, name, public_key, preshared_key, private_key, ipv4, ipv6, enabled, created_at, expires_at, expiry_action, one_time_link, one_time_link_expires_at, dns, allowed_ips, mtu, persistent_keepalive, no_preshared_key, server_allowed_ips, owner_user_id, isolation_exempt FROM clients";
 
 pub async fn list(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<Client>> {
     let rows = sqlx::query(&format!("{SELECT_ALL} ORDER BY created_at"))
@@ -26,8 +38,56 @@ pub async fn list(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<Client>> {
     Ok(rows.iter().map(row_to_client).collect())
 }
 
-pub async fn list_enabled(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<Client>> {
-    let rows = sqlx::query(&format!("{SELECT_ALL} WHERE enabled = 1"))
+pub async fn list_by_interface(
+    pool: &Pool<Sqlite>,
+    interface_id: &str,
+) -> anyhow::Result<Vec<Client>> {
+    let rows = sqlx::query(&format!(
+        "{SELECT_ALL} WHERE interface_id = ? ORDER BY created_at"
+    ))
+    .bind(interface_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(row_to_client).collect())
+}
+
+/// Clients owned by a user, for the self-service portal.
+pub async fn list_by_owner(pool: &Pool<Sqlite>, owner_user_id: i64) -> anyhow::Result<Vec<Client>> {
+    let rows = sqlx::query(&format!(
+        "{SELECT_ALL} WHERE owner_user_id = ? ORDER BY created_at"
+    ))
+    .bind(owner_user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(row_to_client).collect())
+}
+
+/// A client, only if `owner_user_id` owns it.
+pub async fn get_owned(
+    pool: &Pool<Sqlite>,
+    id: &str,
+    owner_user_id: i64,
+) -> anyhow::Result<Option<Client>> {
+    let row = sqlx::query(&format!("{SELECT_ALL} WHERE id = ? AND owner_user_id = ?"))
+        .bind(id)
+        .bind(owner_user_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(row_to_client))
+}
+
+pub async fn list_enabled(pool: &Pool<Sqlite>, interface_id: &str) -> anyhow::Result<Vec<Client>> {
+    let rows = sqlx::query(&format!(
+        "{SELECT_ALL} WHERE interface_id = ? AND enabled = 1"
+    ))
+    .bind(interface_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(row_to_client).collect())
+}
+
+pub async fn list_with_expiry(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<Client>> {
+    let rows = sqlx::query(&format!("{SELECT_ALL} WHERE expires_at IS NOT NULL"))
         .fetch_all(pool)
         .await?;
     Ok(rows.iter().map(row_to_client).collect())
@@ -41,36 +101,87 @@ pub async fn get(pool: &Pool<Sqlite>, id: &str) -> anyhow::Result<Option<Client>
     Ok(row.as_ref().map(row_to_client))
 }
 
-pub async fn create(pool: &Pool<Sqlite>, client: &Client) -> anyhow::Result<()> {
-    sqlx::query(
-        "INSERT INTO clients (id, name, public_key, preshared_key, ipv4, ipv6, enabled, created_at, expires_at, download_url, one_time_link) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+/// Insert a client. With `owner_limit`, only if its owner has fewer clients
+/// than that, counted in the same statement so concurrent inserts can't go
+/// over it. Returns whether the client was inserted.
+pub async fn create(
+    pool: &Pool<Sqlite>,
+    client: &Client,
+    owner_limit: Option<u32>,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        "INSERT INTO clients (id, interface_id, name, public_key, preshared_key, private_key, ipv4, ipv6, enabled, created_at, expires_at, expiry_action, one_time_link, one_time_link_expires_at, dns, allowed_ips, mtu, persistent_keepalive, no_preshared_key, server_allowed_ips, owner_user_id, isolation_exempt) SELECT ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ? WHERE ? IS NULL OR (SELECT COUNT(*) FROM clients WHERE owner_user_id = ?) < ?"
     )
     .bind(&client.id)
+    .bind(&client.interface_id)
     .bind(&client.name)
     .bind(&client.public_key)
     .bind(&client.preshared_key)
+    .bind(&client.private_key)
     .bind(&client.ipv4)
     .bind(&client.ipv6)
     .bind(client.enabled)
     .bind(&client.created_at)
     .bind(&client.expires_at)
-    .bind(&client.download_url)
+    .bind(&client.expiry_action)
     .bind(&client.one_time_link)
+    .bind(&client.one_time_link_expires_at)
+    .bind(&client.dns)
+    .bind(&client.allowed_ips)
+    .bind(client.mtu)
+    .bind(client.persistent_keepalive)
+    .bind(client.no_preshared_key)
+    .bind(&client.server_allowed_ips)
+    .bind(client.owner_user_id)
+    .bind(client.isolation_exempt)
+    .bind(owner_limit)
+    .bind(client.owner_user_id)
+    .bind(owner_limit)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn update(pool: &Pool<Sqlite>, client: &Client) -> anyhow::Result<()> {
+    sqlx::query(
+        "UPDATE clients SET name = ?, enabled = ?, expires_at = ?, expiry_action = ?, dns = ?, allowed_ips = ?, mtu = ?, persistent_keepalive = ?, no_preshared_key = ?, server_allowed_ips = ?, owner_user_id = ?, isolation_exempt = ? WHERE id = ?",
+    )
+    .bind(&client.name)
+    .bind(client.enabled)
+    .bind(&client.expires_at)
+    .bind(&client.expiry_action)
+    .bind(&client.dns)
+    .bind(&client.allowed_ips)
+    .bind(client.mtu)
+    .bind(client.persistent_keepalive)
+    .bind(client.no_preshared_key)
+    .bind(&client.server_allowed_ips)
+    .bind(client.owner_user_id)
+    .bind(client.isolation_exempt)
+    .bind(&client.id)
     .execute(pool)
     .await?;
     Ok(())
 }
 
-pub async fn update(
+pub async fn set_enabled(pool: &Pool<Sqlite>, id: &str, enabled: bool) -> anyhow::Result<()> {
+    sqlx::query("UPDATE clients SET enabled = ? WHERE id = ?")
+        .bind(enabled as i64)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Store a one-time link by the SHA-256 of its token, replacing any other.
+pub async fn set_one_time_link(
     pool: &Pool<Sqlite>,
     id: &str,
-    name: &str,
-    enabled: bool,
-    expires_at: Option<&str>,
+    token_hash: &str,
+    expires_at: &str,
 ) -> anyhow::Result<()> {
-    sqlx::query("UPDATE clients SET name = ?, enabled = ?, expires_at = ? WHERE id = ?")
-        .bind(name)
-        .bind(enabled as i64)
+    sqlx::query("UPDATE clients SET one_time_link = ?, one_time_link_expires_at = ? WHERE id = ?")
+        .bind(token_hash)
         .bind(expires_at)
         .bind(id)
         .execute(pool)
@@ -78,26 +189,63 @@ pub async fn update(
     Ok(())
 }
 
-pub async fn set_enabled(pool: &Pool<Sqlite>, id: &str, enabled: bool) -> anyhow::Result<()> {
-    sqlx::query("UPDATE clients SET enabled = ? WHERE id = ?")
-        .bind(enabled as i64)
+pub async fn find_by_one_time_link(
+    pool: &Pool<Sqlite>,
+    token_hash: &str,
+) -> anyhow::Result<Option<Client>> {
+    let row = sqlx::query(&format!("{SELECT_ALL} WHERE one_time_link = ?"))
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(row_to_client))
+}
+
+/// Invalidate a one-time link. Returns `false` if the token was already
+/// consumed, so concurrent downloads cannot both succeed.
+pub async fn consume_one_time_link(
+    pool: &Pool<Sqlite>,
+    id: &str,
+    token_hash: &str,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        "UPDATE clients SET one_time_link = NULL, one_time_link_expires_at = NULL WHERE id = ? AND one_time_link = ?",
+    )
+    .bind(id)
+    .bind(token_hash)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn delete(pool: &Pool<Sqlite>, id: &str) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM clients WHERE id = ?")
         .bind(id)
         .execute(pool)
         .await?;
     Ok(())
 }
 
-pub async fn delete(pool: &Pool<Sqlite>, id: &str) -> anyhow::Result<()> {
-    sqlx::query("DELETE FROM clients WHERE id = ?")
+pub async fn set_ipv6(pool: &Pool<Sqlite>, id: &str, ipv6: &str) -> anyhow::Result<()> {
+    sqlx::query("UPDATE clients SET ipv6 = ? WHERE id = ?")
+        .bind(ipv6)
         .bind(id)
         .execute(pool)
         .await?;
     Ok(())
 }
 
-pub async fn get_used_ips(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<String>> {
-    let rows = sqlx::query("SELECT ipv4 FROM clients")
+pub async fn get_used_ips(pool: &Pool<Sqlite>, interface_id: &str) -> anyhow::Result<Vec<String>> {
+    let rows = sqlx::query("SELECT ipv4 FROM clients WHERE interface_id = ?")
+        .bind(interface_id)
         .fetch_all(pool)
         .await?;
     Ok(rows.iter().map(|r| r.get::<String, _>("ipv4")).collect())
 }
+
+pub async fn get_used_ipv6(pool: &Pool<Sqlite>, interface_id: &str) -> anyhow::Result<Vec<String>> {
+    let rows = sqlx::query("SELECT ipv6 FROM clients WHERE interface_id = ? AND ipv6 IS NOT NULL")
+        .bind(interface_id)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(|r| r.get::<String, _>("ipv6")).collect())
+}
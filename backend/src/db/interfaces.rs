@@ -1,14 +1,13 @@
+use crate::models::client::Client;
 use crate::models::interface::Interface;
-use sqlx::{Pool, Row, Sqlite};
+use sqlx::sqlite::SqliteArguments;
+use sqlx::{query::Query, Pool, Row, Sqlite};
 
-pub async fn get(pool: &Pool<Sqlite>) -> anyhow::Result<Option<Interface>> {
-    let row = sqlx::query(
-        "SELECT id, name, private_key, public_key, listen_port, ipv4_cidr, ipv6_cidr FROM interfaces LIMIT 1"
-    )
-    .fetch_optional(pool)
-    .await?;
+const SELECT_ALL: &str =
+    "SELECT id, name, private_key, public_key, listen_port, ipv4_cidr, ipv6_cidr, isolate_clients FROM interfaces";
 
-    Ok(row.map(|r| Interface {
+fn row_to_interface(r: &sqlx::sqlite::SqliteRow) -> Interface {
+    Interface {
         id: r.get("id"),
         name: r.get("name"),
         private_key: r.get("private_key"),
@@ -16,12 +15,64 @@ pub async fn get(pool: &Pool<Sqlite>) -> anyhow::Result<Option<Interface>> {
         listen_port: r.get("listen_port"),
         ipv4_cidr: r.get("ipv4_cidr"),
         ipv6_cidr: r.get("ipv6_cidr"),
-    }))
+        isolate_clients: r.get("isolate_clients"),
+    }
+}
+
+/// All interfaces, the default (first created) one first.
+pub async fn list(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<Interface>> {
+    let rows = sqlx::query(&format!("{SELECT_ALL} ORDER BY rowid"))
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(row_to_interface).collect())
+}
+
+/// The default interface, served by the single-interface `/api/interface`
+/// routes and used for new clients that don't name one.
+pub async fn get_default(pool: &Pool<Sqlite>) -> anyhow::Result<Option<Interface>> {
+    let row = sqlx::query(&format!("{SELECT_ALL} ORDER BY rowid LIMIT 1"))
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(row_to_interface))
+}
+
+pub async fn get(pool: &Pool<Sqlite>, id: &str) -> anyhow::Result<Option<Interface>> {
+    let row = sqlx::query(&format!("{SELECT_ALL} WHERE id = ?"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(row_to_interface))
 }
 
 pub async fn upsert(pool: &Pool<Sqlite>, iface: &Interface) -> anyhow::Result<()> {
+    upsert_query(iface).execute(pool).await?;
+    Ok(())
+}
+
+/// Save a changed interface together with the new addresses of the clients
+/// its new networks renumber, so that neither is saved without the other.
+pub async fn update_renumbering(
+    pool: &Pool<Sqlite>,
+    iface: &Interface,
+    renumbered: &[Client],
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    upsert_query(iface).execute(&mut *tx).await?;
+    for client in renumbered {
+        sqlx::query("UPDATE clients SET ipv4 = ?, ipv6 = ? WHERE id = ?")
+            .bind(&client.ipv4)
+            .bind(&client.ipv6)
+            .bind(&client.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+fn upsert_query(iface: &Interface) -> Query<'_, Sqlite, SqliteArguments<'_>> {
     sqlx::query(
-        "INSERT INTO interfaces (id, name, private_key, public_key, listen_port, ipv4_cidr, ipv6_cidr) VALUES (?, ?, ?, ?, ?, ?, ?) ON CONFLICT(id) DO UPDATE SET name=excluded.name, private_key=excluded.private_key, public_key=excluded.public_key, listen_port=excluded.listen_port, ipv4_cidr=excluded.ipv4_cidr, ipv6_cidr=excluded.ipv6_cidr"
+        "INSERT INTO interfaces (id, name, private_key, public_key, listen_port, ipv4_cidr, ipv6_cidr, isolate_clients) VALUES (?, ?, ?, ?, ?, ?, ?, ?) ON CONFLICT(id) DO UPDATE SET name=excluded.name, private_key=excluded.private_key, public_key=excluded.public_key, listen_port=excluded.listen_port, ipv4_cidr=excluded.ipv4_cidr, ipv6_cidr=excluded.ipv6_cidr, isolate_clients=excluded.isolate_clients"
     )
     .bind(&iface.id)
     .bind(&iface.name)
@@ -30,7 +81,13 @@ pub async fn upsert(pool: &Pool<Sqlite>, iface: &Interface) -> anyhow::Result<()
     .bind(iface.listen_port)
     .bind(&iface.ipv4_cidr)
     .bind(&iface.ipv6_cidr)
-    .execute(pool)
-    .await?;
+    .bind(iface.isolate_clients)
+}
+
+pub async fn delete(pool: &Pool<Sqlite>, id: &str) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM interfaces WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
     Ok(())
 }
@@ -4,8 +4,12 @@ use tracing::info;
 
 pub type Db = Arc<Pool<Sqlite>>;
 
+pub mod acl;
+pub mod api_tokens;
+pub mod audit;
 pub mod clients;
 pub mod interfaces;
+pub mod sessions;
 pub mod settings;
 pub mod users;
 
@@ -24,3 +28,11 @@ pub async fn init_db(db_path: &str) -> anyhow::Result<Db> {
 
     Ok(Arc::new(pool))
 }
+
+/// Whether a query failed because it violated a `UNIQUE` constraint.
+pub fn is_unique_violation(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(e)) => e.is_unique_violation(),
+        _ => false,
+    }
+}
@@ -0,0 +1,119 @@
+use crate::models::session::Session;
+use crate::models::user::Role;
+use sqlx::{Pool, Row, Sqlite};
+
+const SELECT_ALL: &str =
+    "SELECT id, username, role, created_at, last_seen_at, expires_at, ip, user_agent FROM sessions";
+
+fn row_to_session(r: &sqlx::sqlite::SqliteRow) -> Session {
+    Session {
+        id: r.get("id"),
+        username: r.get("username"),
+        role: r.get::<String, _>("role").parse().unwrap_or(Role::Viewer),
+        created_at: r.get("created_at"),
+        last_seen_at: r.get("last_seen_at"),
+        expires_at: r.get("expires_at"),
+        ip: r.get("ip"),
+        user_agent: r.get("user_agent"),
+    }
+}
+
+pub async fn list(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<Session>> {
+    let rows = sqlx::query(&format!("{SELECT_ALL} ORDER BY last_seen_at DESC"))
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(row_to_session).collect())
+}
+
+pub async fn list_for_user(pool: &Pool<Sqlite>, username: &str) -> anyhow::Result<Vec<Session>> {
+    let rows = sqlx::query(&format!(
+        "{SELECT_ALL} WHERE username = ? ORDER BY last_seen_at DESC"
+    ))
+    .bind(username)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(row_to_session).collect())
+}
+
+pub async fn get(pool: &Pool<Sqlite>, id: &str) -> anyhow::Result<Option<Session>> {
+    let row = sqlx::query(&format!("{SELECT_ALL} WHERE id = ?"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(row_to_session))
+}
+
+pub async fn create(pool: &Pool<Sqlite>, session: &Session) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO sessions (id, username, role, created_at, last_seen_at, expires_at, ip, user_agent) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&session.id)
+    .bind(&session.username)
+    .bind(session.role.as_str())
+    .bind(&session.created_at)
+    .bind(&session.last_seen_at)
+    .bind(&session.expires_at)
+    .bind(&session.ip)
+    .bind(&session.user_agent)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn touch(
+    pool: &Pool<Sqlite>,
+    id: &str,
+    last_seen_at: &str,
+    expires_at: &str,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE sessions SET last_seen_at = ?, expires_at = ? WHERE id = ?")
+        .bind(last_seen_at)
+        .bind(expires_at)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete(pool: &Pool<Sqlite>, id: &str) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM sessions WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Delete all of a user's sessions except `keep`.
+pub async fn delete_for_user(
+    pool: &Pool<Sqlite>,
+    username: &str,
+    keep: Option<&str>,
+) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM sessions WHERE username = ? AND id IS NOT ?")
+        .bind(username)
+        .bind(keep)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_role_for_user(
+    pool: &Pool<Sqlite>,
+    username: &str,
+    role: Role,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE sessions SET role = ? WHERE username = ?")
+        .bind(role.as_str())
+        .bind(username)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_expired(pool: &Pool<Sqlite>, now: &str) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM sessions WHERE expires_at < ?")
+        .bind(now)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -1,4 +1,6 @@
+use crate::models::settings::{Settings, KEYS};
 use sqlx::{Pool, Row, Sqlite};
+use tracing::warn;
 
 #[allow(dead_code)]
 pub async fn get(pool: &Pool<Sqlite>, key: &str) -> anyhow::Result<Option<String>> {
@@ -20,7 +22,6 @@ pub async fn set(pool: &Pool<Sqlite>, key: &str, value: &str) -> anyhow::Result<
     Ok(())
 }
 
-#[allow(dead_code)]
 pub async fn get_all(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<(String, String)>> {
     let rows = sqlx::query("SELECT key, value FROM config")
         .fetch_all(pool)
@@ -30,3 +31,17 @@ pub async fn get_all(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<(String, String)
         .map(|r| (r.get("key"), r.get("value")))
         .collect())
 }
+
+/// Overlay the values saved in the DB onto `defaults`. Invalid stored values
+/// are skipped with a warning so a bad row can't prevent startup.
+pub async fn load(pool: &Pool<Sqlite>, mut defaults: Settings) -> anyhow::Result<Settings> {
+    for (key, value) in get_all(pool).await? {
+        if !KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        if let Err(e) = defaults.set(&key, &value) {
+            warn!("Ignoring stored setting {}: {}", key, e);
+        }
+    }
+    Ok(defaults)
+}
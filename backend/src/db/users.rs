@@ -1,43 +1,107 @@
-use crate::models::user::User;
+use crate::models::user::{Role, User};
 use sqlx::{Pool, Row, Sqlite};
 
-pub async fn find_by_username(pool: &Pool<Sqlite>, username: &str) -> anyhow::Result<Option<User>> {
-    let row = sqlx::query(
-        "SELECT id, username, password_hash, totp_secret FROM users WHERE username = ?",
-    )
-    .bind(username)
-    .fetch_optional(pool)
-    .await?;
+const SELECT_ALL: &str =
+    "SELECT id, username, password_hash, totp_secret, totp_pending_secret, enabled, role, oidc_issuer, oidc_subject FROM users";
 
-    Ok(row.map(|r| User {
+fn row_to_user(r: &sqlx::sqlite::SqliteRow) -> User {
+    User {
         id: r.get("id"),
         username: r.get("username"),
         password_hash: r.get("password_hash"),
         totp_secret: r.get("totp_secret"),
-    }))
+        totp_pending_secret: r.get("totp_pending_secret"),
+        totp_enabled: r.get::<Option<String>, _>("totp_secret").is_some(),
+        enabled: r.get("enabled"),
+        // Unknown roles (e.g. after a downgrade) get the least access
+        role: r.get::<String, _>("role").parse().unwrap_or(Role::Viewer),
+        oidc_issuer: r.get("oidc_issuer"),
+        oidc_subject: r.get("oidc_subject"),
+    }
+}
+
+pub async fn list(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<User>> {
+    let rows = sqlx::query(&format!("{SELECT_ALL} ORDER BY id"))
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(row_to_user).collect())
+}
+
+pub async fn get(pool: &Pool<Sqlite>, id: i64) -> anyhow::Result<Option<User>> {
+    let row = sqlx::query(&format!("{SELECT_ALL} WHERE id = ?"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(row_to_user))
+}
+
+pub async fn find_by_username(pool: &Pool<Sqlite>, username: &str) -> anyhow::Result<Option<User>> {
+    let row = sqlx::query(&format!("{SELECT_ALL} WHERE username = ?"))
+        .bind(username)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(row_to_user))
+}
+
+pub async fn find_by_oidc(
+    pool: &Pool<Sqlite>,
+    issuer: &str,
+    subject: &str,
+) -> anyhow::Result<Option<User>> {
+    let row = sqlx::query(&format!(
+        "{SELECT_ALL} WHERE oidc_issuer = ? AND oidc_subject = ?"
+    ))
+    .bind(issuer)
+    .bind(subject)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.as_ref().map(row_to_user))
+}
+
+/// Link an account to an SSO identity, or unlink it with `None`.
+pub async fn set_oidc(
+    pool: &Pool<Sqlite>,
+    id: i64,
+    identity: Option<(&str, &str)>,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE users SET oidc_issuer = ?, oidc_subject = ? WHERE id = ?")
+        .bind(identity.map(|(issuer, _)| issuer))
+        .bind(identity.map(|(_, subject)| subject))
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn count(pool: &Pool<Sqlite>) -> anyhow::Result<i64> {
+    let row = sqlx::query("SELECT COUNT(*) AS n FROM users")
+        .fetch_one(pool)
+        .await?;
+    Ok(row.get("n"))
 }
 
-#[allow(dead_code)]
 pub async fn create(
     pool: &Pool<Sqlite>,
     username: &str,
     password_hash: &str,
+    role: Role,
 ) -> anyhow::Result<i64> {
-    let result = sqlx::query("INSERT INTO users (username, password_hash) VALUES (?, ?)")
+    let result = sqlx::query("INSERT INTO users (username, password_hash, role) VALUES (?, ?, ?)")
         .bind(username)
         .bind(password_hash)
+        .bind(role.as_str())
         .execute(pool)
         .await?;
     Ok(result.last_insert_rowid())
 }
 
-#[allow(dead_code)]
+/// Set or clear the TOTP secret, forgetting the last step used with the old one.
 pub async fn set_totp_secret(
     pool: &Pool<Sqlite>,
     id: i64,
     secret: Option<&str>,
 ) -> anyhow::Result<()> {
-    sqlx::query("UPDATE users SET totp_secret = ? WHERE id = ?")
+    sqlx::query("UPDATE users SET totp_secret = ?, totp_last_step = NULL WHERE id = ?")
         .bind(secret)
         .bind(id)
         .execute(pool)
@@ -45,7 +109,71 @@ pub async fn set_totp_secret(
     Ok(())
 }
 
-#[allow(dead_code)]
+/// Record `step` as the last TOTP time step used, if it is later than the
+/// recorded one. Returns `false` for a step already used, so concurrent
+/// logins with the same code cannot both succeed.
+pub async fn claim_totp_step(pool: &Pool<Sqlite>, id: i64, step: i64) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        "UPDATE users SET totp_last_step = ? WHERE id = ? AND (totp_last_step IS NULL OR totp_last_step < ?)",
+    )
+    .bind(step)
+    .bind(id)
+    .bind(step)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn set_totp_pending_secret(
+    pool: &Pool<Sqlite>,
+    id: i64,
+    secret: Option<&str>,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE users SET totp_pending_secret = ? WHERE id = ?")
+        .bind(secret)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Replace a user's recovery codes (an empty slice removes them).
+pub async fn replace_recovery_codes(
+    pool: &Pool<Sqlite>,
+    id: i64,
+    code_hashes: &[String],
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    for hash in code_hashes {
+        sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(id)
+            .bind(hash)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Use up a recovery code. Returns `false` if it doesn't exist or was
+/// already used.
+pub async fn consume_recovery_code(
+    pool: &Pool<Sqlite>,
+    id: i64,
+    code_hash: &str,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query("DELETE FROM recovery_codes WHERE user_id = ? AND code_hash = ?")
+        .bind(id)
+        .bind(code_hash)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
 pub async fn update_password(
     pool: &Pool<Sqlite>,
     id: i64,
@@ -58,3 +186,29 @@ pub async fn update_password(
         .await?;
     Ok(())
 }
+
+pub async fn set_enabled(pool: &Pool<Sqlite>, id: i64, enabled: bool) -> anyhow::Result<()> {
+    sqlx::query("UPDATE users SET enabled = ? WHERE id = ?")
+        .bind(enabled as i64)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_role(pool: &Pool<Sqlite>, id: i64, role: Role) -> anyhow::Result<()> {
+    sqlx::query("UPDATE users SET role = ? WHERE id = ?")
+        .bind(role.as_str())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete(pool: &Pool<Sqlite>, id: i64) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM users WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
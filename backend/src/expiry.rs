@@ -0,0 +1,94 @@
+use chrono::Utc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::audit::{snapshot, Actor, AuditLog};
+use crate::db::Db;
+use crate::models::client::{Client, EXPIRY_DELETE};
+use crate::network::UplinkHandle;
+use crate::wireguard::{interface as wgiface, peers};
+
+/// How often the background task looks for expired clients.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Disable or delete (per `expiry_action`) every client whose expiry has
+/// passed. Only the DB is updated; the affected clients are returned so the
+/// caller can drop them from the kernel.
+pub async fn expire_clients(db: &Db, audit: &AuditLog) -> anyhow::Result<Vec<Client>> {
+    let now = Utc::now();
+    let mut expired = Vec::new();
+    for mut client in crate::db::clients::list_with_expiry(db).await? {
+        if !crate::models::client::expiry_passed(client.expires_at.as_deref(), now) {
+            continue;
+        }
+        let before = snapshot(&client);
+        if client.expiry_action == EXPIRY_DELETE {
+            crate::db::clients::delete(db, &client.id).await?;
+            info!("Deleted expired client {}", client.name);
+            audit
+                .record(
+                    &Actor::system(),
+                    "client.expire",
+                    Some(&client.id),
+                    before,
+                    None,
+                )
+                .await;
+        } else if client.enabled != 0 {
+            crate::db::clients::set_enabled(db, &client.id, false).await?;
+            client.enabled = 0;
+            info!("Disabled expired client {}", client.name);
+            let after = snapshot(&client);
+            audit
+                .record(
+                    &Actor::system(),
+                    "client.expire",
+                    Some(&client.id),
+                    before,
+                    after,
+                )
+                .await;
+        } else {
+            continue;
+        }
+        expired.push(client);
+    }
+    Ok(expired)
+}
+
+/// Run `expire_clients` periodically, removing expired peers from the kernel
+/// and the isolation exemptions and ACLs of deleted ones.
+pub fn spawn(db: Db, audit: AuditLog, uplinks: UplinkHandle) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = run_once(&db, &audit, &uplinks).await {
+                warn!("Client expiry check failed: {e:#}");
+            }
+        }
+    });
+}
+
+async fn run_once(db: &Db, audit: &AuditLog, uplinks: &UplinkHandle) -> anyhow::Result<()> {
+    let expired = expire_clients(db, audit).await?;
+    if expired.is_empty() {
+        return Ok(());
+    }
+    // Their ACL rows are gone already, so reload whenever one was deleted
+    let deleted = expired.iter().any(|c| c.expiry_action == EXPIRY_DELETE);
+    for client in expired {
+        let Some(iface) = crate::db::interfaces::get(db, &client.interface_id).await? else {
+            continue;
+        };
+        peers::remove_peer(&iface.name, &client.public_key)
+            .unwrap_or_else(|e| warn!("Failed to remove expired peer {}: {}", client.name, e));
+        wgiface::update_routes(&iface.name, &[], &client.routed_subnets())
+            .await
+            .unwrap_or_else(|e| warn!("Failed to remove routes of expired {}: {e:#}", client.name));
+    }
+    if deleted {
+        crate::network::refresh_nat(db, uplinks).await?;
+    }
+    Ok(())
+}
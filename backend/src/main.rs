@@ -4,20 +4,34 @@ use tokio::signal::unix::{signal, SignalKind};
 use tracing::info;
 
 mod api;
+mod audit;
 mod config;
+mod crypto;
 mod db;
 mod error;
+mod expiry;
 mod models;
+mod network;
+mod validate;
 mod wireguard;
 
 use api::session::SessionStore;
 pub use config::AppConfig;
 
+/// Account created from `PASSWORD_HASH` when the users table is empty.
+const BOOTSTRAP_USERNAME: &str = "admin";
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: db::Db,
     pub config: std::sync::Arc<AppConfig>,
     pub sessions: SessionStore,
+    pub login_throttle: api::throttle::LoginThrottle,
+    pub oidc: Option<api::oidc::Oidc>,
+    pub secrets: crypto::SecretBox,
+    pub settings: models::settings::SettingsHandle,
+    pub uplinks: network::UplinkHandle,
+    pub audit: audit::AuditLog,
 }
 
 #[tokio::main]
@@ -34,88 +48,115 @@ async fn main() -> anyhow::Result<()> {
     info!("Starting wg-easy-rs on port {}", config.port);
     info!("WireGuard host: {}", config.wg_host);
 
-    // 3. Open SQLite pool + run migrations
+    // 3. Open SQLite pool + run migrations, load the key sealing client secrets
     let db = db::init_db(&config.db_path).await?;
-
-    // 4. Load or seed interface config
-    let iface = match db::interfaces::get(&db).await? {
-        Some(i) => i,
-        None => {
-            info!("No interface found, seeding defaults");
-            let (private_key, public_key) = wireguard::keys::generate_keypair();
-            let iface = models::interface::Interface {
-                id: uuid::Uuid::new_v4().to_string(),
-                name: "wg0".to_string(),
-                private_key,
-                public_key,
-                listen_port: config.wg_port as i64,
-                ipv4_cidr: "10.8.0.0/24".to_string(),
-                ipv6_cidr: None,
-            };
-            db::interfaces::upsert(&db, &iface).await?;
-            iface
+    let secrets =
+        crypto::SecretBox::from_config(&config).context("Failed to load encryption key")?;
+    let settings = models::settings::Settings::from_config(&config)?;
+    let settings = db::settings::load(&db, settings)
+        .await
+        .context("Failed to load settings")?;
+    let audit = audit::AuditLog::new(db.clone(), config.audit_log_file.as_deref()).await?;
+
+    // First run: create the admin account from PASSWORD_HASH
+    if db::users::count(&db).await? == 0 {
+        match &config.password_hash {
+            Some(hash) => {
+                db::users::create(&db, BOOTSTRAP_USERNAME, hash, models::user::Role::Admin).await?;
+                info!("Created user {BOOTSTRAP_USERNAME} from PASSWORD_HASH");
+            }
+            None if !config.insecure => anyhow::bail!("PASSWORD_HASH is required to create the first user when INSECURE=false (set a bcrypt hash or use INSECURE=true for development only)"),
+            None => {}
         }
+    }
+
+    // 4. Load interface configs, seeding a default wg0 on first start
+    if db::interfaces::get_default(&db).await?.is_none() {
+        info!("No interface found, seeding defaults");
+        let (private_key, public_key) = wireguard::keys::generate_keypair();
+        let iface = models::interface::Interface {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: "wg0".to_string(),
+            private_key,
+            public_key,
+            listen_port: config.wg_port as i64,
+            ipv4_cidr: "10.8.0.0/24".to_string(),
+            ipv6_cidr: None,
+            isolate_clients: 0,
+        };
+        db::interfaces::upsert(&db, &iface).await?;
+    }
+
+    // Apply client expiry before enabled peers are loaded into the kernel
+    expiry::expire_clients(&db, &audit)
+        .await
+        .context("Failed to apply client expiry")?;
+
+    // Uplink for NAT: configured, or the default routes' interfaces
+    let uplinks = match &config.wg_outbound_iface {
+        Some(name) => network::Uplinks::fixed(name),
+        None => network::Uplinks::detect().await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to detect the outbound interface: {e:#}");
+            network::Uplinks::default()
+        }),
     };
+    info!("Outbound interface: {uplinks}");
+    let uplinks = std::sync::Arc::new(std::sync::RwLock::new(uplinks));
 
-    // 5–8. WireGuard interface setup (requires NET_ADMIN + Linux kernel)
+    // 5–9. WireGuard interface setup and NAT (requires NET_ADMIN + Linux kernel)
     #[cfg(target_os = "linux")]
     {
-        use wireguard::interface as wgiface;
-        use wireguard::peers;
+        // Firewall rules go in before any peer is loaded: clients must not
+        // come up without the isolation and ACLs configured for them
+        if let Err(e) = network::refresh_nat(&db, &uplinks).await {
+            if network::restricts_clients(&db).await? {
+                return Err(e.context(
+                    "Failed to apply the client isolation and ACL firewall rules; \
+                     refusing to load peers without them",
+                ));
+            }
+            tracing::warn!("NAT setup failed (may need root): {}", e);
+        }
 
-        let (conn, netlink_handle, _) =
-            rtnetlink::new_connection().context("Failed to open rtnetlink connection")?;
-        tokio::spawn(conn);
+        let interfaces = db::interfaces::list(&db).await?;
+        network::check_forwarding(
+            config.wg_enable_ip_forward,
+            interfaces.iter().any(|iface| iface.ipv6_cidr.is_some()),
+        );
+        network::check_forward_policy();
 
-        // Create interface if it doesn't exist
-        if !wgiface::link_exists(&netlink_handle, &iface.name).await {
-            wgiface::create_wireguard_link(&netlink_handle, &iface.name)
+        for iface in interfaces {
+            network::bring_up(&db, &iface)
                 .await
-                .context("Failed to create WireGuard interface")?;
+                .with_context(|| format!("Failed to bring up {}", iface.name))?;
         }
 
-        // Configure WireGuard (private key + listen port)
-        peers::configure_interface(&iface.name, &iface.private_key, iface.listen_port as u16)
-            .context("Failed to configure WireGuard interface")?;
-
-        // Assign IP address + bring up + add route
-        let idx = wgiface::get_link_index(&netlink_handle, &iface.name).await?;
-        let net: ipnet::IpNet = iface.ipv4_cidr.parse().context("Invalid interface CIDR")?;
-        wgiface::assign_address(&netlink_handle, idx, &net)
-            .await
-            .unwrap_or_else(|e| tracing::warn!("assign_address: {} (may already exist)", e));
-        wgiface::set_link_up(&netlink_handle, idx).await?;
-        wgiface::add_route(&netlink_handle, idx, &net)
-            .await
-            .unwrap_or_else(|e| tracing::warn!("add_route: {} (may already exist)", e));
-
-        // Bulk sync enabled peers
-        let clients = db::clients::list_enabled(&db).await?;
-        let peer_tuples: Vec<(String, String, Vec<String>)> = clients
-            .into_iter()
-            .map(|c| {
-                (
-                    c.public_key,
-                    c.preshared_key,
-                    vec![format!("{}/32", c.ipv4)],
-                )
-            })
-            .collect();
-        peers::sync_peers(&iface.name, &peer_tuples).context("Failed to sync peers")?;
-
-        // 9. Setup NAT
-        let outbound = std::env::var("WG_OUTBOUND_IFACE").unwrap_or_else(|_| "eth0".to_string());
-        wireguard::nat::setup_nat(&iface.ipv4_cidr, &outbound)
-            .unwrap_or_else(|e| tracing::warn!("NAT setup failed (may need root): {}", e));
+        if config.wg_outbound_iface.is_none() {
+            network::spawn_uplink_monitor(db.clone(), uplinks.clone())
+                .unwrap_or_else(|e| tracing::warn!("Cannot follow uplink changes: {e:#}"));
+        }
     }
 
     // 10. Build app state
     let state = AppState {
         db: db.clone(),
         config: std::sync::Arc::new(config.clone()),
-        sessions: api::session::new_store(),
+        sessions: api::session::SessionStore::new(db.clone(), &config),
+        login_throttle: api::throttle::LoginThrottle::new(&config),
+        oidc: config
+            .oidc
+            .clone()
+            .map(|oidc| api::oidc::Oidc::new(oidc, &secrets))
+            .transpose()?,
+        secrets,
+        settings: std::sync::Arc::new(std::sync::RwLock::new(settings)),
+        audit: audit.clone(),
+        uplinks,
     };
 
+    // Disable or delete clients as they expire
+    expiry::spawn(db.clone(), audit, state.uplinks.clone());
+
     // 11. Prometheus metrics
     let prom_builder = metrics_exporter_prometheus::PrometheusBuilder::new();
     let prom_handle = prom_builder
@@ -131,9 +172,12 @@ async fn main() -> anyhow::Result<()> {
     info!("Listening on {addr}");
     let listener = tokio::net::TcpListener::bind(addr).await?;
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
 
     // Graceful shutdown: teardown NAT and WireGuard interface
     #[cfg(target_os = "linux")]
@@ -141,12 +185,11 @@ async fn main() -> anyhow::Result<()> {
         wireguard::nat::teardown_nat()
             .unwrap_or_else(|e| tracing::warn!("NAT teardown failed: {}", e));
 
-        let (conn, handle, _) =
-            rtnetlink::new_connection().context("Failed to open rtnetlink for shutdown")?;
-        tokio::spawn(conn);
-        wireguard::interface::delete_link(&handle, "wg0")
-            .await
-            .unwrap_or_else(|e| tracing::warn!("Failed to delete wg0: {}", e));
+        for iface in db::interfaces::list(&db).await? {
+            network::tear_down(&iface)
+                .await
+                .unwrap_or_else(|e| tracing::warn!("Failed to delete {}: {}", iface.name, e));
+        }
     }
 
     db.close().await;
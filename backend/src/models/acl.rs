@@ -0,0 +1,158 @@
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+/// One entry of a client's ACL over the traffic it sends through the server.
+/// Rules are checked in order and the first match decides; once a client has
+/// rules, traffic none of them match is denied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AclRule {
+    pub action: AclAction,
+    /// Destination network, e.g. `10.0.0.0/8` or `::/0`.
+    pub cidr: String,
+    /// `None` matches every protocol.
+    #[serde(default)]
+    pub protocol: Option<AclProtocol>,
+    /// Destination ports; TCP and UDP only. `None` matches every port.
+    #[serde(default)]
+    pub ports: Option<PortRange>,
+}
+
+impl AclRule {
+    /// Parsed `cidr`.
+    pub fn network(&self) -> Option<IpNet> {
+        self.cidr.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AclAction {
+    Allow,
+    Deny,
+}
+
+impl AclAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AclAction::Allow => "allow",
+            AclAction::Deny => "deny",
+        }
+    }
+}
+
+impl std::str::FromStr for AclAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(AclAction::Allow),
+            "deny" => Ok(AclAction::Deny),
+            _ => Err(format!("Unknown ACL action: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AclProtocol {
+    Tcp,
+    Udp,
+    /// ICMP for IPv4 networks, ICMPv6 for IPv6 ones.
+    Icmp,
+}
+
+impl AclProtocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AclProtocol::Tcp => "tcp",
+            AclProtocol::Udp => "udp",
+            AclProtocol::Icmp => "icmp",
+        }
+    }
+}
+
+impl std::str::FromStr for AclProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(AclProtocol::Tcp),
+            "udp" => Ok(AclProtocol::Udp),
+            "icmp" => Ok(AclProtocol::Icmp),
+            _ => Err(format!("Unknown ACL protocol: {s}")),
+        }
+    }
+}
+
+/// An inclusive port range, written `"443"` or `"8000-8080"` in JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortRange {
+    pub from: u16,
+    pub to: u16,
+}
+
+impl std::str::FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let port = |p: &str| {
+            p.trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|p| *p != 0)
+                .ok_or_else(|| format!("Invalid port range: {s}"))
+        };
+        let (from, to) = match s.split_once('-') {
+            Some((from, to)) => (port(from)?, port(to)?),
+            None => (port(s)?, port(s)?),
+        };
+        if from > to {
+            return Err(format!("Invalid port range: {s}"));
+        }
+        Ok(PortRange { from, to })
+    }
+}
+
+impl TryFrom<String> for PortRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PortRange> for String {
+    fn from(range: PortRange) -> Self {
+        if range.from == range.to {
+            range.from.to_string()
+        } else {
+            format!("{}-{}", range.from, range.to)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_range() {
+        assert_eq!(
+            "443".parse::<PortRange>().unwrap(),
+            PortRange { from: 443, to: 443 }
+        );
+        assert_eq!(
+            "8000-8080".parse::<PortRange>().unwrap(),
+            PortRange {
+                from: 8000,
+                to: 8080
+            }
+        );
+        assert!("0".parse::<PortRange>().is_err());
+        assert!("8080-8000".parse::<PortRange>().is_err());
+        assert!("65536".parse::<PortRange>().is_err());
+        assert!("http".parse::<PortRange>().is_err());
+        assert_eq!(String::from(PortRange { from: 53, to: 53 }), "53");
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::user::Role;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub user_id: i64,
+    pub username: String,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+}
+
+impl ApiToken {
+    /// The most a request with this token may do; the owner's own role still
+    /// caps it.
+    pub fn max_role(&self) -> Role {
+        self.scopes
+            .iter()
+            .map(|s| s.role())
+            .max()
+            .unwrap_or(Role::Viewer)
+    }
+
+    /// The role a request with this token gets when its owner has `owner`.
+    pub fn role_for(&self, owner: Role) -> Role {
+        owner.min(self.max_role())
+    }
+}
+
+/// What an API token may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Read-only, like the viewer role.
+    Read,
+    /// Create, edit and delete clients and download their configs.
+    Clients,
+    /// Everything the owner may do.
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Clients => "clients",
+            Scope::Admin => "admin",
+        }
+    }
+
+    pub fn role(self) -> Role {
+        match self {
+            Scope::Read => Role::Viewer,
+            Scope::Clients => Role::Operator,
+            Scope::Admin => Role::Admin,
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "clients" => Ok(Scope::Clients),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(format!("Unknown scope: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(scopes: &[Scope]) -> ApiToken {
+        ApiToken {
+            id: "t".to_string(),
+            user_id: 1,
+            username: "alice".to_string(),
+            name: "ci".to_string(),
+            token_hash: String::new(),
+            scopes: scopes.to_vec(),
+            created_at: String::new(),
+            expires_at: None,
+            last_used_at: None,
+        }
+    }
+
+    #[test]
+    fn test_scope_role() {
+        assert_eq!(Scope::Read.role(), Role::Viewer);
+        assert_eq!(Scope::Clients.role(), Role::Operator);
+        assert_eq!(Scope::Admin.role(), Role::Admin);
+    }
+
+    #[test]
+    fn test_max_role() {
+        assert_eq!(token(&[]).max_role(), Role::Viewer);
+        assert_eq!(token(&[Scope::Read]).max_role(), Role::Viewer);
+        assert_eq!(
+            token(&[Scope::Clients, Scope::Read]).max_role(),
+            Role::Operator
+        );
+        assert_eq!(token(&[Scope::Read, Scope::Admin]).max_role(), Role::Admin);
+    }
+
+    #[test]
+    fn test_role_capped_by_owner() {
+        // An admin-scoped token can't do more than its owner
+        assert_eq!(token(&[Scope::Admin]).role_for(Role::Viewer), Role::Viewer);
+        assert_eq!(token(&[Scope::Admin]).role_for(Role::Member), Role::Member);
+        // Nor more than its scopes allow
+        assert_eq!(token(&[Scope::Read]).role_for(Role::Admin), Role::Viewer);
+        assert_eq!(
+            token(&[Scope::Clients]).role_for(Role::Operator),
+            Role::Operator
+        );
+    }
+}
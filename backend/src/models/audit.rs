@@ -0,0 +1,16 @@
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: String,
+    /// Username, or `None` for actions the server took itself.
+    pub actor: Option<String>,
+    /// Dotted name such as `client.create`.
+    pub action: String,
+    pub target_id: Option<String>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub ip: Option<String>,
+}
@@ -1,16 +1,85 @@
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 
+pub const EXPIRY_DISABLE: &str = "disable";
+pub const EXPIRY_DELETE: &str = "delete";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Client {
     pub id: String,
+    pub interface_id: String,
     pub name: String,
     pub public_key: String,
+    /// Only ever sent to the device, in its config.
+    #[serde(skip_serializing)]
     pub preshared_key: String,
+    /// Sealed with `crypto::SecretBox`; `None` when the server does not hold the key.
+    #[serde(skip)]
+    pub private_key: Option<String>,
     pub ipv4: String,
     pub ipv6: Option<String>,
     pub enabled: i64,
     pub created_at: String,
     pub expires_at: Option<String>,
-    pub download_url: Option<String>,
+    /// `"disable"` or `"delete"`, applied once `expires_at` has passed.
+    pub expiry_action: String,
+    /// Derived from `expires_at` when the row is loaded.
+    #[serde(skip_deserializing)]
+    pub expired: bool,
+    /// SHA-256 of the pending one-time link's token. The link itself is
+    /// only returned by `generate_one_time_link`: it downloads the config,
+    /// private key included, without logging in.
+    #[serde(skip_serializing)]
     pub one_time_link: Option<String>,
+    pub one_time_link_expires_at: Option<String>,
+    // Overrides of the global client config; `None` uses the default.
+    pub dns: Option<String>,
+    pub allowed_ips: Option<String>,
+    pub mtu: Option<i64>,
+    pub persistent_keepalive: Option<i64>,
+    /// Set to 1 to run this peer without a preshared key.
+    pub no_preshared_key: i64,
+    /// Comma-separated subnets routed to this peer (site-to-site).
+    pub server_allowed_ips: Option<String>,
+    /// User who manages this client from the self-service portal.
+    pub owner_user_id: Option<i64>,
+    /// Set to 1 to let every client reach this one, even on an interface
+    /// that isolates clients (e.g. a shared printer or server).
+    pub isolation_exempt: i64,
+}
+
+impl Client {
+    /// Tunnel addresses and routed subnets of this peer, in the form
+    /// WireGuard expects.
+    pub fn peer_allowed_ips(&self) -> Vec<String> {
+        let mut ips = vec![format!("{}/32", self.ipv4)];
+        if let Some(ipv6) = &self.ipv6 {
+            ips.push(format!("{ipv6}/128"));
+        }
+        ips.extend(self.routed_subnets().iter().map(ToString::to_string));
+        ips
+    }
+
+    /// Parsed `server_allowed_ips`.
+    pub fn routed_subnets(&self) -> Vec<IpNet> {
+        self.server_allowed_ips
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|s| s.trim().parse().ok())
+            .collect()
+    }
+
+    /// The preshared key to use, unless it is switched off for this client.
+    pub fn active_preshared_key(&self) -> Option<&str> {
+        (self.no_preshared_key == 0).then_some(self.preshared_key.as_str())
+    }
+}
+
+/// Whether an RFC 3339 expiry timestamp lies at or before `now`.
+pub fn expiry_passed(expires_at: Option<&str>, now: DateTime<Utc>) -> bool {
+    expires_at
+        .and_then(|ts| DateTime::parse_from_rfc3339(ts).ok())
+        .is_some_and(|ts| ts <= now)
 }
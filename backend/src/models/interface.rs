@@ -1,3 +1,5 @@
+use anyhow::Context;
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -9,4 +11,24 @@ pub struct Interface {
     pub listen_port: i64,
     pub ipv4_cidr: String,
     pub ipv6_cidr: Option<String>,
+    /// Set to 1 to block traffic between VPN addresses through this
+    /// interface, except to and from exempt clients.
+    pub isolate_clients: i64,
+}
+
+impl Interface {
+    /// The interface's VPN networks: IPv4, then IPv6 if configured.
+    pub fn networks(&self) -> anyhow::Result<Vec<IpNet>> {
+        let mut networks = vec![self
+            .ipv4_cidr
+            .parse()
+            .with_context(|| format!("Invalid CIDR {}", self.ipv4_cidr))?];
+        if let Some(cidr) = &self.ipv6_cidr {
+            networks.push(
+                cidr.parse()
+                    .with_context(|| format!("Invalid IPv6 CIDR {cidr}"))?,
+            );
+        }
+        Ok(networks)
+    }
 }
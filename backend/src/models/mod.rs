@@ -1,4 +1,8 @@
+pub mod acl;
+pub mod api_token;
+pub mod audit;
 pub mod client;
 pub mod interface;
+pub mod session;
 pub mod settings;
 pub mod user;
@@ -0,0 +1,18 @@
+use serde::Serialize;
+
+use crate::models::user::Role;
+
+/// A login session. `require_auth` puts the caller's in the request
+/// extensions.
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    /// SHA-256 of the cookie token; safe to show and used to revoke.
+    pub id: String,
+    pub username: String,
+    pub role: Role,
+    pub created_at: String,
+    pub last_seen_at: String,
+    pub expires_at: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
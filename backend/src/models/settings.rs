@@ -1,8 +1,103 @@
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::{validate, AppConfig};
+
+/// Runtime-editable settings, mostly used when rendering client configs.
+/// Defaults come from the environment; values saved in the `config` table override them.
+#[derive(Debug, Clone, Serialize)]
 pub struct Settings {
-    pub key: String,
-    pub value: String,
+    pub wg_host: String,
+    /// Public UDP port advertised for the default interface.
+    pub wg_port: u16,
+    pub wg_default_dns: String,
+    pub wg_allowed_ips: String,
+    pub wg_mtu: Option<u16>,
+    pub wg_persistent_keepalive: u16,
+    /// Devices a member may create from the self-service portal.
+    pub member_max_clients: u32,
+}
+
+/// Names of all settings, as stored in the `config` table.
+pub const KEYS: &[&str] = &[
+    "wg_host",
+    "wg_port",
+    "wg_default_dns",
+    "wg_allowed_ips",
+    "wg_mtu",
+    "wg_persistent_keepalive",
+    "member_max_clients",
+];
+
+/// Live settings shared by all handlers; edits apply to the next config built.
+pub type SettingsHandle = Arc<RwLock<Settings>>;
+
+impl Settings {
+    /// Defaults from the environment, validated like stored values.
+    pub fn from_config(config: &AppConfig) -> anyhow::Result<Self> {
+        let mut settings = Self {
+            wg_host: config.wg_host.clone(),
+            wg_port: config.wg_port,
+            wg_default_dns: config.wg_default_dns.clone(),
+            wg_allowed_ips: config.wg_allowed_ips.clone(),
+            wg_mtu: config.wg_mtu,
+            wg_persistent_keepalive: config.wg_persistent_keepalive,
+            member_max_clients: config.member_max_clients,
+        };
+        for key in KEYS {
+            let value = settings.get(key).unwrap_or_default();
+            settings
+                .set(key, &value)
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", key.to_uppercase(), e))?;
+        }
+        Ok(settings)
+    }
+
+    /// Validate and store a setting given in its stored string form. An
+    /// empty `wg_mtu` clears it.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "wg_host" => self.wg_host = validate::host(value)?,
+            "wg_port" => self.wg_port = validate::port(parse_int(key, value)?)?,
+            "wg_default_dns" => self.wg_default_dns = validate::dns_servers(value)?,
+            "wg_allowed_ips" => self.wg_allowed_ips = validate::cidr_list(value)?,
+            "wg_mtu" => {
+                self.wg_mtu = match value.trim() {
+                    "" => None,
+                    v => Some(validate::mtu(parse_int(key, v)?)? as u16),
+                }
+            }
+            "wg_persistent_keepalive" => {
+                self.wg_persistent_keepalive =
+                    validate::persistent_keepalive(parse_int(key, value)?)? as u16
+            }
+            "member_max_clients" => {
+                self.member_max_clients = u32::try_from(parse_int(key, value)?)
+                    .map_err(|_| format!("{key} must not be negative"))?
+            }
+            _ => return Err(format!("Unknown setting: {key}")),
+        }
+        Ok(())
+    }
+
+    /// A setting in the string form `set` accepts.
+    pub fn get(&self, key: &str) -> Option<String> {
+        Some(match key {
+            "wg_host" => self.wg_host.clone(),
+            "wg_port" => self.wg_port.to_string(),
+            "wg_default_dns" => self.wg_default_dns.clone(),
+            "wg_allowed_ips" => self.wg_allowed_ips.clone(),
+            "wg_mtu" => self.wg_mtu.map(|v| v.to_string()).unwrap_or_default(),
+            "wg_persistent_keepalive" => self.wg_persistent_keepalive.to_string(),
+            "member_max_clients" => self.member_max_clients.to_string(),
+            _ => return None,
+        })
+    }
+}
+
+fn parse_int(key: &str, value: &str) -> Result<i64, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("{key} must be a number"))
 }
@@ -4,6 +4,57 @@ use serde::{Deserialize, Serialize};
 pub struct User {
     pub id: i64,
     pub username: String,
+    #[serde(skip_serializing)]
     pub password_hash: String,
+    #[serde(skip_serializing)]
     pub totp_secret: Option<String>,
+    #[serde(skip_serializing)]
+    pub totp_pending_secret: Option<String>,
+    #[serde(skip_deserializing)]
+    pub totp_enabled: bool,
+    pub enabled: i64,
+    pub role: Role,
+    /// The account's identity at the OpenID Connect provider, the only way
+    /// SSO logs into it.
+    pub oidc_issuer: Option<String>,
+    pub oidc_subject: Option<String>,
+}
+
+/// Access levels, lowest first; each role may do everything the ones before
+/// it may.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Only their own devices, through the `/api/me/clients` portal.
+    Member,
+    /// Read-only access to clients, interfaces, stats and config.
+    Viewer,
+    /// Manage clients, but not interfaces, config or users.
+    Operator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Member => "member",
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "member" => Ok(Role::Member),
+            "viewer" => Ok(Role::Viewer),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role: {s}")),
+        }
+    }
 }
@@ -0,0 +1,330 @@
+use anyhow::Context;
+use futures::StreamExt;
+use ipnet::IpNet;
+use rtnetlink::IpVersion;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::db::Db;
+use crate::models::interface::Interface;
+use crate::wireguard::{interface as wgiface, nat, peers};
+
+/// Create the interface's link if needed, configure it from the DB and load
+/// its enabled peers and their routes.
+pub async fn bring_up(db: &Db, iface: &Interface) -> anyhow::Result<()> {
+    let handle = wgiface::connect()?;
+
+    // Create interface if it doesn't exist
+    if !wgiface::link_exists(&handle, &iface.name).await {
+        wgiface::create_wireguard_link(&handle, &iface.name)
+            .await
+            .context("Failed to create WireGuard interface")?;
+    }
+
+    // Configure WireGuard (private key + listen port)
+    peers::configure_interface(&iface.name, &iface.private_key, iface.listen_port as u16)
+        .context("Failed to configure WireGuard interface")?;
+
+    // Assign IP addresses + bring up + add routes
+    let idx = wgiface::get_link_index(&handle, &iface.name).await?;
+    let networks = iface.networks()?;
+    for net in &networks {
+        let addr = wgiface::server_address(net).context("Interface CIDR has no hosts")?;
+        wgiface::assign_address(&handle, idx, &addr)
+            .await
+            .unwrap_or_else(|e| warn!("assign_address: {} (may already exist)", e));
+    }
+    wgiface::set_link_up(&handle, idx).await?;
+    for net in &networks {
+        wgiface::add_route(&handle, idx, net)
+            .await
+            .unwrap_or_else(|e| warn!("add_route: {} (may already exist)", e));
+    }
+
+    // Bulk sync enabled peers, giving pre-existing clients IPv6 addresses first
+    crate::api::clients::backfill_ipv6(db, iface)
+        .await
+        .context("Failed to assign client IPv6 addresses")?;
+    crate::api::clients::sync_peers(db, iface)
+        .await
+        .context("Failed to sync peers")?;
+
+    // Routes to subnets behind site-to-site peers
+    for client in crate::db::clients::list_enabled(db, &iface.id).await? {
+        for net in client.routed_subnets() {
+            wgiface::add_route(&handle, idx, &net)
+                .await
+                .unwrap_or_else(|e| warn!("add_route: {} (may already exist)", e));
+        }
+    }
+    Ok(())
+}
+
+/// Move the server address and VPN route of a running interface from network
+/// `old` to `new`.
+pub async fn move_network(name: &str, old: &IpNet, new: &IpNet) -> anyhow::Result<()> {
+    let handle = wgiface::connect()?;
+    let idx = wgiface::get_link_index(&handle, name).await?;
+    if let Some(addr) = wgiface::server_address(old) {
+        wgiface::remove_address(&handle, idx, &addr)
+            .await
+            .unwrap_or_else(|e| warn!("remove_address: {} (may already be gone)", e));
+    }
+    wgiface::del_route(&handle, idx, old)
+        .await
+        .unwrap_or_else(|e| warn!("del_route: {} (may already be gone)", e));
+
+    let addr = wgiface::server_address(new).context("Interface CIDR has no hosts")?;
+    wgiface::assign_address(&handle, idx, &addr).await?;
+    wgiface::add_route(&handle, idx, new)
+        .await
+        .unwrap_or_else(|e| warn!("add_route: {} (may already exist)", e));
+    Ok(())
+}
+
+/// Delete the interface's link, which also drops its addresses and routes.
+pub async fn tear_down(iface: &Interface) -> anyhow::Result<()> {
+    let handle = wgiface::connect()?;
+    wgiface::delete_link(&handle, &iface.name).await
+}
+
+/// How long to let a burst of route changes settle before looking again.
+const ROUTE_SETTLE: Duration = Duration::from_secs(2);
+
+/// The interfaces VPN traffic leaves the host through, per address family.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Uplinks {
+    pub ipv4: Option<String>,
+    pub ipv6: Option<String>,
+}
+
+pub type UplinkHandle = Arc<RwLock<Uplinks>>;
+
+impl Uplinks {
+    /// The same interface for both families, from `WG_OUTBOUND_IFACE`.
+    pub fn fixed(name: &str) -> Self {
+        Self {
+            ipv4: Some(name.to_string()),
+            ipv6: Some(name.to_string()),
+        }
+    }
+
+    /// The interfaces of the IPv4 and IPv6 default routes.
+    pub async fn detect() -> anyhow::Result<Self> {
+        let handle = wgiface::connect()?;
+        Ok(Self {
+            ipv4: wgiface::default_route_link(&handle, IpVersion::V4).await?,
+            ipv6: wgiface::default_route_link(&handle, IpVersion::V6).await?,
+        })
+    }
+}
+
+impl std::fmt::Display for Uplinks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = |n: &Option<String>| n.clone().unwrap_or_else(|| "none".to_string());
+        write!(f, "IPv4 {}, IPv6 {}", name(&self.ipv4), name(&self.ipv6))
+    }
+}
+
+/// Held across each `refresh_nat`, so reloads don't interleave and the last
+/// one to run sees the current uplinks and DB.
+static NAT_REFRESH: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Re-apply NAT, forwarding, isolation and ACL rules for every interface in
+/// the DB.
+pub async fn refresh_nat(db: &Db, uplinks: &UplinkHandle) -> anyhow::Result<()> {
+    let _guard = NAT_REFRESH.lock().await;
+    let uplinks = uplinks.read().unwrap().clone();
+    let mut acls = std::collections::HashMap::<String, Vec<_>>::new();
+    for (client_id, rule) in crate::db::acl::list_all(db).await? {
+        acls.entry(client_id).or_default().push(rule);
+    }
+
+    let mut interfaces = Vec::new();
+    for iface in crate::db::interfaces::list(db).await? {
+        let mut exempt = Vec::new();
+        let mut client_acls = Vec::new();
+        for client in crate::db::clients::list_by_interface(db, &iface.id).await? {
+            let mut addresses: Vec<IpAddr> = vec![client.ipv4.parse()?];
+            if let Some(ipv6) = &client.ipv6 {
+                addresses.push(ipv6.parse()?);
+            }
+            if client.isolation_exempt != 0 {
+                exempt.extend(&addresses);
+            }
+            if let Some(rules) = acls.remove(&client.id) {
+                // Packets from the client's routed subnets come through its
+                // tunnel too, so they must pass the same ACL
+                let mut sources: Vec<IpNet> = addresses.into_iter().map(IpNet::from).collect();
+                sources.extend(client.routed_subnets());
+                client_acls.push(nat::ClientAcl { sources, rules });
+            }
+        }
+        interfaces.push(nat::NatInterface {
+            networks: iface.networks()?,
+            isolate_clients: iface.isolate_clients != 0,
+            exempt,
+            acls: client_acls,
+            name: iface.name,
+        });
+    }
+    nat::setup_nat(
+        &interfaces,
+        uplinks.ipv4.as_deref(),
+        uplinks.ipv6.as_deref(),
+    )
+}
+
+/// Whether any interface isolates its clients or any client has an ACL, so
+/// that running without the firewall rules would let clients reach more
+/// than they may.
+pub async fn restricts_clients(db: &Db) -> anyhow::Result<bool> {
+    let isolated = crate::db::interfaces::list(db)
+        .await?
+        .iter()
+        .any(|iface| iface.isolate_clients != 0);
+    Ok(isolated || !crate::db::acl::list_all(db).await?.is_empty())
+}
+
+const IPV4_FORWARD: &str = "/proc/sys/net/ipv4/ip_forward";
+const IPV6_FORWARD: &str = "/proc/sys/net/ipv6/conf/all/forwarding";
+/// Tables legacy (x_tables) iptables has loaded.
+const IPTABLES_LEGACY_TABLES: &str = "/proc/net/ip_tables_names";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Sysctl {
+    AlreadyOn,
+    Enabled,
+    Off,
+}
+
+/// Check that the kernel forwards IPv4 (and, if any interface has an IPv6
+/// network, IPv6) packets, switching it on when `enable` is set. Without it
+/// clients connect but reach nothing beyond this host, so problems are
+/// logged with what to do about them; the server itself keeps running.
+pub fn check_forwarding(enable: bool, ipv6: bool) {
+    let mut switches = vec![("net.ipv4.ip_forward", IPV4_FORWARD)];
+    if ipv6 {
+        switches.push(("net.ipv6.conf.all.forwarding", IPV6_FORWARD));
+    }
+    for (name, path) in switches {
+        match ensure_on(Path::new(path), enable) {
+            Ok(Sysctl::AlreadyOn) => {}
+            Ok(Sysctl::Enabled) => info!("Enabled {name}"),
+            Ok(Sysctl::Off) => warn!(
+                "{name} is off and WG_ENABLE_IP_FORWARD=false: clients will only reach this host. \
+                 Turn it on with `sysctl -w {name}=1`"
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("{name} is not available ({path} is missing); is the protocol disabled?")
+            }
+            Err(e) => tracing::error!(
+                "{name} is off and could not be turned on ({e}): clients will only reach this \
+                 host. Run with CAP_NET_ADMIN and a writable /proc/sys, or set it outside the \
+                 container, e.g. `docker run --sysctl {name}=1`"
+            ),
+        }
+    }
+}
+
+/// Warn about firewalls that drop forwarded packets by default, which the
+/// accept rules in our own forward chain can't override: clients would
+/// connect but reach nothing beyond this host.
+pub fn check_forward_policy() {
+    match nat::forward_drop_chains() {
+        Ok(chains) => {
+            for chain in chains {
+                warn!(
+                    "nftables chain {chain} drops forwarded packets by default: VPN traffic \
+                     is dropped there unless it accepts it, e.g. with \
+                     `nft insert rule {chain} iifname \"wg*\" accept` and \
+                     `nft insert rule {chain} oifname \"wg*\" ct state established,related accept`"
+                )
+            }
+        }
+        Err(e) => warn!("Could not check the nftables forward policy: {e:#}"),
+    }
+    // Legacy iptables keeps its rules out of nftables; its policy can't be
+    // read without the iptables tools
+    let legacy = std::fs::read_to_string(IPTABLES_LEGACY_TABLES).unwrap_or_default();
+    if legacy.lines().any(|table| table == "filter") {
+        warn!(
+            "Legacy iptables is in use; if its FORWARD policy is DROP (e.g. under Docker), \
+             VPN traffic is dropped. Accept it with `iptables -I FORWARD -i wg+ -j ACCEPT` and \
+             `iptables -I FORWARD -o wg+ -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT`"
+        );
+    }
+}
+
+/// Read a boolean sysctl at `path` and write `1` to it if it's off and
+/// `enable` is set.
+fn ensure_on(path: &Path, enable: bool) -> std::io::Result<Sysctl> {
+    if std::fs::read_to_string(path)?.trim() != "0" {
+        return Ok(Sysctl::AlreadyOn);
+    }
+    if !enable {
+        return Ok(Sysctl::Off);
+    }
+    std::fs::write(path, "1")?;
+    Ok(Sysctl::Enabled)
+}
+
+/// Watch the routing table and, when the default routes move to another
+/// interface, record the new uplinks and re-apply NAT for them.
+pub fn spawn_uplink_monitor(db: Db, uplinks: UplinkHandle) -> anyhow::Result<()> {
+    use netlink_sys::{AsyncSocket, SocketAddr};
+    use rtnetlink::constants::{RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_ROUTE};
+
+    let (mut conn, _, mut messages) =
+        rtnetlink::new_connection().context("Failed to open rtnetlink connection")?;
+    conn.socket_mut()
+        .socket_mut()
+        .bind(&SocketAddr::new(0, RTMGRP_IPV4_ROUTE | RTMGRP_IPV6_ROUTE))
+        .context("Failed to subscribe to route changes")?;
+    tokio::spawn(conn);
+
+    tokio::spawn(async move {
+        while messages.next().await.is_some() {
+            tokio::time::sleep(ROUTE_SETTLE).await;
+            while messages.try_recv().is_ok() {}
+
+            let detected = match Uplinks::detect().await {
+                Ok(detected) => detected,
+                Err(e) => {
+                    warn!("Failed to look up default routes: {e:#}");
+                    continue;
+                }
+            };
+            if *uplinks.read().unwrap() == detected {
+                continue;
+            }
+            info!("Uplink changed: {detected}");
+            *uplinks.write().unwrap() = detected;
+            refresh_nat(&db, &uplinks)
+                .await
+                .unwrap_or_else(|e| warn!("NAT setup failed (may need root): {}", e));
+        }
+        warn!("Route monitor stopped; uplink changes will not be followed");
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ensure_on() {
+        let path = std::env::temp_dir().join(format!("wg-easy-sysctl-{}", std::process::id()));
+        std::fs::write(&path, "0\n").unwrap();
+        assert_eq!(ensure_on(&path, false).unwrap(), Sysctl::Off);
+        assert_eq!(ensure_on(&path, true).unwrap(), Sysctl::Enabled);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1");
+        assert_eq!(ensure_on(&path, true).unwrap(), Sysctl::AlreadyOn);
+        std::fs::remove_file(&path).unwrap();
+        assert!(ensure_on(&path, true).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::models::acl::{AclProtocol, AclRule};
+
+/// Smallest MTU WireGuard can carry IPv6 over.
+pub const MIN_MTU: u16 = 1280;
+pub const MAX_MTU: u16 = 9000;
+
+/// Validate a comma-separated list of DNS server IPs, returning it normalized
+/// to the `a, b` form used in configs.
+pub fn dns_servers(value: &str) -> Result<String, String> {
+    let servers = split_list(value)
+        .map(|s| {
+            s.parse::<IpAddr>()
+                .map(|ip| ip.to_string())
+                .map_err(|_| format!("Invalid DNS server IP: {s}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if servers.is_empty() {
+        return Err("At least one DNS server is required".to_string());
+    }
+    Ok(servers.join(", "))
+}
+
+/// Validate a comma-separated list of CIDRs, returning it normalized.
+pub fn cidr_list(value: &str) -> Result<String, String> {
+    let nets = parse_cidr_list(value)?;
+    if nets.is_empty() {
+        return Err("At least one CIDR is required".to_string());
+    }
+    Ok(nets
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+/// Parse a comma-separated list of CIDRs; an empty list is allowed.
+pub fn parse_cidr_list(value: &str) -> Result<Vec<IpNet>, String> {
+    split_list(value)
+        .map(|s| s.parse::<IpNet>().map_err(|_| format!("Invalid CIDR: {s}")))
+        .collect()
+}
+
+/// Validate the public host clients connect to: an IP address or a DNS name.
+pub fn host(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value.parse::<IpAddr>().is_ok() {
+        return Ok(value.to_string());
+    }
+    let valid_label = |label: &str| {
+        (1..=63).contains(&label.len())
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    if value.len() <= 253 && value.split('.').all(valid_label) {
+        Ok(value.to_string())
+    } else {
+        Err(format!("Invalid host: {value}"))
+    }
+}
+
+pub fn port(value: i64) -> Result<u16, String> {
+    u16::try_from(value)
+        .ok()
+        .filter(|port| *port != 0)
+        .ok_or_else(|| "Port must be between 1 and 65535".to_string())
+}
+
+pub fn mtu(value: i64) -> Result<i64, String> {
+    if (MIN_MTU as i64..=MAX_MTU as i64).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("MTU must be between {MIN_MTU} and {MAX_MTU}"))
+    }
+}
+
+pub fn persistent_keepalive(value: i64) -> Result<i64, String> {
+    if (0..=u16::MAX as i64).contains(&value) {
+        Ok(value)
+    } else {
+        Err("PersistentKeepalive must be between 0 and 65535 seconds".to_string())
+    }
+}
+
+/// Validate an ACL rule, normalizing its CIDR to the network address.
+pub fn acl_rule(mut rule: AclRule) -> Result<AclRule, String> {
+    let network = rule
+        .cidr
+        .trim()
+        .parse::<IpNet>()
+        .map_err(|_| format!("Invalid CIDR: {}", rule.cidr))?;
+    rule.cidr = network.trunc().to_string();
+    if rule.ports.is_some() && !matches!(rule.protocol, Some(AclProtocol::Tcp | AclProtocol::Udp)) {
+        return Err("Ports need protocol \"tcp\" or \"udp\"".to_string());
+    }
+    Ok(rule)
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dns_servers() {
+        assert_eq!(
+            dns_servers("1.1.1.1,2606:4700::1111").unwrap(),
+            "1.1.1.1, 2606:4700::1111"
+        );
+        assert!(dns_servers("1.1.1.1, dns.example").is_err());
+        assert!(dns_servers(" , ").is_err());
+    }
+
+    #[test]
+    fn test_host() {
+        assert_eq!(host(" vpn.example.com ").unwrap(), "vpn.example.com");
+        assert!(host("203.0.113.7").is_ok());
+        assert!(host("").is_err());
+        assert!(host("vpn..example").is_err());
+        assert!(host("vpn.example.com:51820").is_err());
+    }
+
+    #[test]
+    fn test_cidr_list() {
+        assert_eq!(cidr_list("0.0.0.0/0, ::/0").unwrap(), "0.0.0.0/0, ::/0");
+        assert!(cidr_list("10.0.0.0/33").is_err());
+    }
+
+    #[test]
+    fn test_acl_rule() {
+        let rule: AclRule = serde_json::from_value(serde_json::json!({
+            "action": "allow", "cidr": "10.1.2.3/8", "protocol": "tcp", "ports": "443"
+        }))
+        .unwrap();
+        assert_eq!(acl_rule(rule.clone()).unwrap().cidr, "10.0.0.0/8");
+        assert!(acl_rule(AclRule {
+            protocol: None,
+            ..rule.clone()
+        })
+        .is_err());
+        assert!(acl_rule(AclRule {
+            cidr: "intranet".to_string(),
+            ..rule
+        })
+        .is_err());
+    }
+}
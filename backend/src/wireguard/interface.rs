@@ -1,8 +1,31 @@
 use anyhow::anyhow;
 use futures::TryStreamExt;
 use ipnet::IpNet;
-use rtnetlink::Handle;
-use tracing::info;
+use netlink_packet_route::link::LinkAttribute;
+use netlink_packet_route::route::{RouteAttribute, RouteHeader, RouteMessage, RouteType};
+use rtnetlink::{Handle, IpVersion};
+use std::net::IpAddr;
+use tracing::{info, warn};
+
+/// Open an rtnetlink connection, driving it on a background task.
+pub fn connect() -> anyhow::Result<Handle> {
+    let (conn, handle, _) = rtnetlink::new_connection()
+        .map_err(|e| anyhow!("Failed to open rtnetlink connection: {}", e))?;
+    tokio::spawn(conn);
+    Ok(handle)
+}
+
+/// Address the server itself uses inside a VPN network: the first host after
+/// the network address (e.g. `10.8.0.1/24`, `fd42::1/64`).
+pub fn server_address(network: &IpNet) -> Option<IpNet> {
+    let addr = network.hosts().find(|host| *host != network.network())?;
+    IpNet::new(addr, network.prefix_len()).ok()
+}
+
+/// Whether `addr` is the network address or the server's own address.
+pub fn is_reserved(network: &IpNet, addr: IpAddr) -> bool {
+    addr == network.network() || server_address(network).map(|s| s.addr()) == Some(addr)
+}
 
 /// Create a WireGuard network interface.
 pub async fn create_wireguard_link(handle: &Handle, name: &str) -> anyhow::Result<()> {
@@ -41,6 +64,22 @@ pub async fn assign_address(handle: &Handle, index: u32, net: &IpNet) -> anyhow:
     Ok(())
 }
 
+/// Remove an address added with `assign_address`.
+pub async fn remove_address(handle: &Handle, index: u32, net: &IpNet) -> anyhow::Result<()> {
+    let message = handle
+        .address()
+        .add(index, net.addr(), net.prefix_len())
+        .message_mut()
+        .clone();
+    handle
+        .address()
+        .del(message)
+        .execute()
+        .await
+        .map_err(|e| anyhow!("Failed to remove address {}: {}", net, e))?;
+    Ok(())
+}
+
 /// Add a route for the VPN subnet.
 pub async fn add_route(handle: &Handle, index: u32, network: &IpNet) -> anyhow::Result<()> {
     match network {
@@ -70,6 +109,56 @@ pub async fn add_route(handle: &Handle, index: u32, network: &IpNet) -> anyhow::
     Ok(())
 }
 
+/// Remove a route added with `add_route`.
+pub async fn del_route(handle: &Handle, index: u32, network: &IpNet) -> anyhow::Result<()> {
+    let message = match network {
+        IpNet::V4(net) => handle
+            .route()
+            .add()
+            .v4()
+            .destination_prefix(net.network(), net.prefix_len())
+            .output_interface(index)
+            .message_mut()
+            .clone(),
+        IpNet::V6(net) => handle
+            .route()
+            .add()
+            .v6()
+            .destination_prefix(net.network(), net.prefix_len())
+            .output_interface(index)
+            .message_mut()
+            .clone(),
+    };
+    handle
+        .route()
+        .del(message)
+        .execute()
+        .await
+        .map_err(|e| anyhow!("Failed to delete route {}: {}", network, e))?;
+    Ok(())
+}
+
+/// Add and remove routes to a link by name on a fresh connection. Failures
+/// are logged rather than returned, as the routes may already be in place.
+pub async fn update_routes(name: &str, add: &[IpNet], remove: &[IpNet]) -> anyhow::Result<()> {
+    if add.is_empty() && remove.is_empty() {
+        return Ok(());
+    }
+    let handle = connect()?;
+    let index = get_link_index(&handle, name).await?;
+    for net in remove {
+        del_route(&handle, index, net)
+            .await
+            .unwrap_or_else(|e| warn!("del_route: {} (may already be gone)", e));
+    }
+    for net in add {
+        add_route(&handle, index, net)
+            .await
+            .unwrap_or_else(|e| warn!("add_route: {} (may already exist)", e));
+    }
+    Ok(())
+}
+
 /// Delete a network interface by name.
 pub async fn delete_link(handle: &Handle, name: &str) -> anyhow::Result<()> {
     let index = get_link_index(handle, name).await?;
@@ -97,7 +186,120 @@ pub async fn get_link_index(handle: &Handle, name: &str) -> anyhow::Result<u32>
     }
 }
 
+/// Resolve an interface index to its name.
+pub async fn get_link_name(handle: &Handle, index: u32) -> anyhow::Result<String> {
+    let mut links = handle.link().get().match_index(index).execute();
+    let msg = links
+        .try_next()
+        .await
+        .map_err(|e| anyhow!("rtnetlink error: {}", e))?
+        .ok_or_else(|| anyhow!("Interface {} not found", index))?;
+    msg.attributes
+        .into_iter()
+        .find_map(|attr| match attr {
+            LinkAttribute::IfName(name) => Some(name),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("Interface {} has no name", index))
+}
+
+/// Name of the interface the main table's default route goes out of, if
+/// there is one for `version`.
+pub async fn default_route_link(
+    handle: &Handle,
+    version: IpVersion,
+) -> anyhow::Result<Option<String>> {
+    let routes: Vec<RouteMessage> = handle
+        .route()
+        .get(version)
+        .execute()
+        .try_collect()
+        .await
+        .map_err(|e| anyhow!("rtnetlink error: {}", e))?;
+    match default_route_oif(&routes) {
+        Some(index) => Ok(Some(get_link_name(handle, index).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Output interface of the preferred (lowest metric) default route in the
+/// main table; for a multipath route, its first next hop's.
+fn default_route_oif(routes: &[RouteMessage]) -> Option<u32> {
+    routes
+        .iter()
+        .filter(|route| {
+            route.header.destination_prefix_length == 0
+                && route.header.kind == RouteType::Unicast
+                && route_table(route) == u32::from(RouteHeader::RT_TABLE_MAIN)
+        })
+        .filter_map(|route| {
+            let mut oif = None;
+            let mut priority = 0;
+            for attr in &route.attributes {
+                match attr {
+                    RouteAttribute::Oif(index) => oif = Some(*index),
+                    RouteAttribute::MultiPath(hops) if oif.is_none() => {
+                        oif = hops.first().map(|hop| hop.interface_index)
+                    }
+                    RouteAttribute::Priority(p) => priority = *p,
+                    _ => {}
+                }
+            }
+            oif.map(|oif| (priority, oif))
+        })
+        .min_by_key(|(priority, _)| *priority)
+        .map(|(_, oif)| oif)
+}
+
+/// Tables above 255 are only given by the `RTA_TABLE` attribute.
+fn route_table(route: &RouteMessage) -> u32 {
+    route
+        .attributes
+        .iter()
+        .find_map(|attr| match attr {
+            RouteAttribute::Table(table) => Some(*table),
+            _ => None,
+        })
+        .unwrap_or(u32::from(route.header.table))
+}
+
 /// Check whether a WireGuard interface already exists.
 pub async fn link_exists(handle: &Handle, name: &str) -> bool {
     get_link_index(handle, name).await.is_ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(prefix_len: u8, table: u8, attributes: Vec<RouteAttribute>) -> RouteMessage {
+        let mut route = RouteMessage::default();
+        route.header.destination_prefix_length = prefix_len;
+        route.header.table = table;
+        route.header.kind = RouteType::Unicast;
+        route.attributes = attributes;
+        route
+    }
+
+    #[test]
+    fn test_default_route_oif_prefers_lowest_metric() {
+        let main = RouteHeader::RT_TABLE_MAIN;
+        let routes = vec![
+            // A more specific route and another table's default are ignored
+            route(24, main, vec![RouteAttribute::Oif(7)]),
+            route(0, 100, vec![RouteAttribute::Oif(8)]),
+            route(
+                0,
+                main,
+                vec![RouteAttribute::Oif(3), RouteAttribute::Priority(600)],
+            ),
+            route(
+                0,
+                main,
+                vec![RouteAttribute::Oif(2), RouteAttribute::Priority(100)],
+            ),
+        ];
+        assert_eq!(default_route_oif(&routes), Some(2));
+        assert_eq!(default_route_oif(&routes[..2]), None);
+    }
+}
@@ -5,7 +5,7 @@ use x25519_dalek::{PublicKey, StaticSecret};
 /// Generate a WireGuard keypair.
 /// Returns `(private_key_base64, public_key_base64)`.
 pub fn generate_keypair() -> (String, String) {
-    let private = StaticSecret::random_from_rng(&mut rand::thread_rng());
+    let private = StaticSecret::random_from_rng(rand::thread_rng());
     let public = PublicKey::from(&private);
     let private_b64 = STANDARD.encode(private.as_bytes());
     let public_b64 = STANDARD.encode(public.as_bytes());
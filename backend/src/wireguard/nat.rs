@@ -1,25 +1,110 @@
 use anyhow::anyhow;
-use tracing::info;
+use ipnet::IpNet;
+use rustables::data_type::DataType;
+use rustables::expr::{
+    Bitwise, Cmp, CmpOp, HighLevelPayload, IPv4HeaderField, IPv6HeaderField, Immediate, Lookup,
+    Meta, MetaType, NetworkHeaderField, TCPHeaderField, TransportHeaderField, VerdictKind,
+};
+use rustables::set::SetBuilder;
+use rustables::{Batch, Chain, MsgType, ProtocolFamily, Rule, Table};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::models::acl::{AclAction, AclProtocol, AclRule};
+use tracing::{info, warn};
 
 const TABLE_NAME: &str = "wg_easy_nat";
 const CHAIN_NAME: &str = "postrouting";
+const FORWARD_CHAIN_NAME: &str = "forward";
+/// `NFPROTO_IPV4` / `NFPROTO_IPV6` from `linux/netfilter.h`.
+const NFPROTO_IPV4: u8 = 2;
+const NFPROTO_IPV6: u8 = 10;
+/// Kernel limit on interface names, including the NUL.
+const IFNAMSIZ: usize = 16;
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const IPPROTO_ICMPV6: u8 = 58;
+/// From `linux/netlink.h`, `linux/netfilter/nfnetlink.h`,
+/// `linux/netfilter/nf_tables.h` and `linux/netfilter.h`.
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_DUMP: u16 = 0x300;
+const NFNL_SUBSYS_NFTABLES: u16 = 10;
+const NFT_MSG_GETCHAIN: u16 = 4;
+const NFTA_CHAIN_TABLE: u16 = 1;
+const NFTA_CHAIN_NAME: u16 = 3;
+const NFTA_CHAIN_HOOK: u16 = 4;
+const NFTA_CHAIN_POLICY: u16 = 5;
+const NFTA_HOOK_HOOKNUM: u16 = 1;
+const NF_INET_FORWARD: u32 = 2;
+const NF_DROP: u32 = 0;
+
+/// A WireGuard interface and the VPN networks on it.
+#[derive(Debug, Clone)]
+pub struct NatInterface {
+    pub name: String,
+    pub networks: Vec<IpNet>,
+    /// Drop traffic between VPN addresses entering or leaving the interface.
+    pub isolate_clients: bool,
+    /// Tunnel addresses of clients every VPN address may still reach.
+    pub exempt: Vec<IpAddr>,
+    /// Clients on the interface with an ACL.
+    pub acls: Vec<ClientAcl>,
+}
+
+/// A client's tunnel addresses and routed subnets, and the ACL the packets
+/// it sends from them must pass.
+#[derive(Debug, Clone)]
+pub struct ClientAcl {
+    pub sources: Vec<IpNet>,
+    pub rules: Vec<AclRule>,
+}
 
-/// Set up NAT MASQUERADE using nftables for the WireGuard subnet.
+/// Which address of a packet a rule matches.
+#[derive(Debug, Clone, Copy)]
+enum Addr {
+    Source,
+    Destination,
+}
+
+/// Set up NAT MASQUERADE and forwarding using nftables for the WireGuard
+/// interfaces, through the uplink for each address family:
+///
+/// * `postrouting`: one rule per subnet, masquerading packets from it that
+///   leave through the uplink. Other traffic the host routes is left alone.
+/// * `forward`: accepts packets from each WireGuard interface to the uplink,
+///   and established/related packets coming back, so a host whose own
+///   `inet`/`ip` filter rules accept by default but are missing these still
+///   forwards VPN traffic. Drop rules in other tables still apply.
+///   Interfaces with `isolate_clients` get drop rules ahead of these for
+///   packets between VPN addresses (of any interface) entering or leaving
+///   them, after accepting packets to exempt clients and their replies.
+///   Packets from clients with an ACL are checked against it first (see
+///   `add_acls`).
+///
+/// The table is deleted and recreated in the same batch, so calling this
+/// again after interfaces are added or removed, or the uplink changes,
+/// replaces the rules atomically.
 ///
 /// # Arguments
-/// * `wg_cidr` - The WireGuard subnet in CIDR notation (e.g. `"10.8.0.0/24"`)
-/// * `outbound_iface` - The physical network interface used for outbound traffic (e.g. `"eth0"`)
-pub fn setup_nat(wg_cidr: &str, outbound_iface: &str) -> anyhow::Result<()> {
-    use rustables::expr::Masquerade;
-    use rustables::{
-        Batch, Chain, ChainPolicy, ChainType, Hook, HookClass, MsgType, ProtocolFamily, Rule, Table,
-    };
+/// * `interfaces` - Every WireGuard interface and its subnets (e.g. `wg0`, `["10.8.0.0/24"]`)
+/// * `ipv4_iface` / `ipv6_iface` - The physical network interfaces used for
+///   outbound traffic (e.g. `"eth0"`); subnets of a family without one aren't NATed
+pub fn setup_nat(
+    interfaces: &[NatInterface],
+    ipv4_iface: Option<&str>,
+    ipv6_iface: Option<&str>,
+) -> anyhow::Result<()> {
+    use rustables::{ChainPolicy, ChainType, Hook, HookClass};
 
     let mut batch = Batch::new();
 
-    // Create table
+    // Create table, dropping any rules from a previous setup
     let table = Table::new(ProtocolFamily::Inet).with_name(TABLE_NAME.to_string());
     batch.add(&table, MsgType::Add);
+    batch.add(&table, MsgType::Del);
+    batch.add(&table, MsgType::Add);
 
     // Create nat chain hooked at postrouting
     let hook = Hook::new(HookClass::PostRouting, 100);
@@ -30,27 +115,471 @@ pub fn setup_nat(wg_cidr: &str, outbound_iface: &str) -> anyhow::Result<()> {
         .with_policy(ChainPolicy::Accept);
     batch.add(&chain, MsgType::Add);
 
-    // Add masquerade rule
-    let rule = Rule::new(&chain)
-        .map_err(|e| anyhow!("Rule build error: {:?}", e))?
-        .with_expr(Masquerade {});
-    batch.add(&rule, MsgType::Add);
+    // Filter chain at forward, with the standard filter priority
+    let forward = Chain::new(&table)
+        .with_name(FORWARD_CHAIN_NAME.to_string())
+        .with_hook(Hook::new(HookClass::Forward, 0))
+        .with_type(ChainType::Filter)
+        .with_policy(ChainPolicy::Accept);
+    batch.add(&forward, MsgType::Add);
+
+    add_acls(&mut batch, &table, &forward, interfaces)?;
+    for rule in isolation_rules(&forward, interfaces)? {
+        batch.add(&rule, MsgType::Add);
+    }
+
+    let mut configured = Vec::new();
+    for iface in interfaces {
+        for network in &iface.networks {
+            let (family, uplink) = match network {
+                IpNet::V4(_) => (NFPROTO_IPV4, ipv4_iface),
+                IpNet::V6(_) => (NFPROTO_IPV6, ipv6_iface),
+            };
+            let Some(uplink) = uplink else {
+                warn!("No outbound interface for {network}; not masquerading it");
+                continue;
+            };
+            batch.add(&masquerade_rule(&chain, network, uplink)?, MsgType::Add);
+            for rule in forward_rules(&forward, family, &iface.name, uplink)? {
+                batch.add(&rule, MsgType::Add);
+            }
+            configured.push(format!("{network} via {uplink}"));
+        }
+    }
 
     batch
         .send()
         .map_err(|e| anyhow!("nftables batch send error: {:?}", e))?;
 
     info!(
-        "nftables NAT MASQUERADE configured for {} via {}",
-        wg_cidr, outbound_iface
+        "nftables NAT MASQUERADE and forwarding configured for {}",
+        configured.join(", ")
     );
     Ok(())
 }
 
+/// `<ip|ip6> saddr <network> oifname <outbound_iface> masquerade`
+fn masquerade_rule(chain: &Chain, network: &IpNet, outbound_iface: &str) -> anyhow::Result<Rule> {
+    let rule = match_network(new_rule(chain)?, Addr::Source, network)?
+        .with_expr(Meta::new(MetaType::OifName))
+        .with_expr(Cmp::new(CmpOp::Eq, ifname(outbound_iface)?))
+        .with_expr(rustables::expr::Masquerade {});
+    Ok(rule)
+}
+
+/// Compile the clients' ACLs. Clients with the same rules share a regular
+/// chain `acl_<n>` and sets `acl_<n>_v4` / `acl_<n>_v6` of their tunnel
+/// addresses, which `<ip|ip6> saddr @acl_<n>_v4 jump acl_<n>` sends the
+/// packets they forward through. Routed subnets get a
+/// `<ip|ip6> saddr <subnet> jump acl_<n>` rule each, as the sets hold single
+/// addresses only. There, replies (`ct state
+/// established,related`) and allowed packets `return`, so the rules after the
+/// jump still apply; denied packets and those no rule matches are dropped.
+fn add_acls(
+    batch: &mut Batch,
+    table: &Table,
+    forward: &Chain,
+    interfaces: &[NatInterface],
+) -> anyhow::Result<()> {
+    for (n, (rules, sources)) in acl_groups(interfaces).into_iter().enumerate() {
+        let name = format!("acl_{n}");
+        let chain = Chain::new(table).with_name(name.clone());
+        batch.add(&chain, MsgType::Add);
+
+        let replies =
+            established(new_rule(&chain)?)?.with_expr(Immediate::new_verdict(VerdictKind::Return));
+        batch.add(&replies, MsgType::Add);
+        for rule in rules {
+            batch.add(&acl_rule(&chain, rule)?, MsgType::Add);
+        }
+        let rest = new_rule(&chain)?.with_expr(Immediate::new_verdict(VerdictKind::Drop));
+        batch.add(&rest, MsgType::Add);
+
+        let (hosts, subnets): (Vec<&IpNet>, Vec<&IpNet>) = sources
+            .iter()
+            .partition(|net| net.prefix_len() == net.max_prefix_len());
+        let v4: Vec<Ipv4Addr> = hosts
+            .iter()
+            .filter_map(|net| match net {
+                IpNet::V4(net) => Some(net.addr()),
+                IpNet::V6(_) => None,
+            })
+            .collect();
+        let v6: Vec<Ipv6Addr> = hosts
+            .iter()
+            .filter_map(|net| match net {
+                IpNet::V4(_) => None,
+                IpNet::V6(net) => Some(net.addr()),
+            })
+            .collect();
+        let saddr4 = NetworkHeaderField::IPv4(IPv4HeaderField::Saddr);
+        let saddr6 = NetworkHeaderField::IPv6(IPv6HeaderField::Saddr);
+        jump_by_source(
+            batch,
+            table,
+            forward,
+            NFPROTO_IPV4,
+            saddr4,
+            &v4,
+            &name,
+            "v4",
+        )?;
+        jump_by_source(
+            batch,
+            table,
+            forward,
+            NFPROTO_IPV6,
+            saddr6,
+            &v6,
+            &name,
+            "v6",
+        )?;
+        for subnet in subnets {
+            let rule = match_network(new_rule(forward)?, Addr::Source, subnet)?.with_expr(
+                Immediate::new_verdict(VerdictKind::Jump {
+                    chain: name.clone(),
+                }),
+            );
+            batch.add(&rule, MsgType::Add);
+        }
+    }
+    Ok(())
+}
+
+/// Clients' source networks, grouped by identical non-empty ACLs.
+fn acl_groups(interfaces: &[NatInterface]) -> Vec<(&[AclRule], Vec<IpNet>)> {
+    let mut groups: Vec<(&[AclRule], Vec<IpNet>)> = Vec::new();
+    for acl in interfaces
+        .iter()
+        .flat_map(|iface| &iface.acls)
+        .filter(|acl| !acl.rules.is_empty())
+    {
+        match groups.iter_mut().find(|(rules, _)| *rules == acl.rules) {
+            Some((_, sources)) => sources.extend(&acl.sources),
+            None => groups.push((&acl.rules, acl.sources.clone())),
+        }
+    }
+    groups
+}
+
+/// Add the set `<chain>_<suffix>` of `addresses` and
+/// `<ip|ip6> saddr @<chain>_<suffix> jump <chain>`, unless `addresses` is empty.
+#[allow(clippy::too_many_arguments)]
+fn jump_by_source<K: DataType>(
+    batch: &mut Batch,
+    table: &Table,
+    forward: &Chain,
+    family: u8,
+    saddr: NetworkHeaderField,
+    addresses: &[K],
+    chain: &str,
+    suffix: &str,
+) -> anyhow::Result<()> {
+    if addresses.is_empty() {
+        return Ok(());
+    }
+    let mut builder = SetBuilder::<K>::new(format!("{chain}_{suffix}"), table)
+        .map_err(|e| anyhow!("Set build error: {:?}", e))?;
+    for addr in addresses {
+        builder.add(addr);
+    }
+    let (mut set, elements) = builder.finish();
+    // SetBuilder leaves the family unset, and the kernel won't find the table
+    set.family = ProtocolFamily::Inet;
+    batch.add(&set, MsgType::Add);
+    batch.add(&elements, MsgType::Add);
+
+    let rule = new_rule(forward)?
+        .with_expr(Meta::new(MetaType::NfProto))
+        .with_expr(Cmp::new(CmpOp::Eq, [family]))
+        .with_expr(HighLevelPayload::Network(saddr).build())
+        .with_expr(Lookup::new(&set).map_err(|e| anyhow!("Rule build error: {:?}", e))?)
+        .with_expr(Immediate::new_verdict(VerdictKind::Jump {
+            chain: chain.to_string(),
+        }));
+    batch.add(&rule, MsgType::Add);
+    Ok(())
+}
+
+/// `<ip|ip6> daddr <cidr> [meta l4proto <p> [th dport <from>-<to>]] <return|drop>`
+fn acl_rule(chain: &Chain, acl: &AclRule) -> anyhow::Result<Rule> {
+    let network = acl
+        .network()
+        .ok_or_else(|| anyhow!("Invalid ACL CIDR {}", acl.cidr))?;
+    let mut rule = match_network(new_rule(chain)?, Addr::Destination, &network)?;
+    if let Some(protocol) = acl.protocol {
+        let number = match (protocol, network) {
+            (AclProtocol::Tcp, _) => IPPROTO_TCP,
+            (AclProtocol::Udp, _) => IPPROTO_UDP,
+            (AclProtocol::Icmp, IpNet::V4(_)) => IPPROTO_ICMP,
+            (AclProtocol::Icmp, IpNet::V6(_)) => IPPROTO_ICMPV6,
+        };
+        rule = rule
+            .with_expr(Meta::new(MetaType::L4Proto))
+            .with_expr(Cmp::new(CmpOp::Eq, [number]));
+    }
+    if let Some(ports) = acl.ports {
+        // TCP and UDP both keep the destination port at offset 2
+        let dport = TransportHeaderField::Tcp(TCPHeaderField::Dport);
+        rule = rule
+            .with_expr(HighLevelPayload::Transport(dport).build())
+            .with_expr(Cmp::new(CmpOp::Gte, ports.from.to_be_bytes()))
+            .with_expr(Cmp::new(CmpOp::Lte, ports.to.to_be_bytes()));
+    }
+    let verdict = match acl.action {
+        AclAction::Allow => VerdictKind::Return,
+        AclAction::Deny => VerdictKind::Drop,
+    };
+    Ok(rule.with_expr(Immediate::new_verdict(verdict)))
+}
+
+/// For every exempt client address `a` and every isolated interface `wg`:
+///
+/// * `<ip|ip6> daddr a accept`
+/// * `<ip|ip6> saddr a ct state established,related accept`
+/// * `iifname <wg> <ip|ip6> daddr <VPN network> drop`
+/// * `oifname <wg> <ip|ip6> saddr <VPN network> drop`
+///
+/// The accepts only skip the drops below them, as the chain accepts anyway.
+fn isolation_rules(chain: &Chain, interfaces: &[NatInterface]) -> anyhow::Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    if !interfaces.iter().any(|iface| iface.isolate_clients) {
+        return Ok(rules);
+    }
+
+    for addr in interfaces.iter().flat_map(|iface| &iface.exempt) {
+        let host = IpNet::from(*addr);
+        rules.push(
+            match_network(new_rule(chain)?, Addr::Destination, &host)?
+                .with_expr(Immediate::new_verdict(VerdictKind::Accept)),
+        );
+        rules.push(
+            established(match_network(new_rule(chain)?, Addr::Source, &host)?)?
+                .with_expr(Immediate::new_verdict(VerdictKind::Accept)),
+        );
+    }
+
+    let networks: Vec<&IpNet> = interfaces
+        .iter()
+        .flat_map(|iface| &iface.networks)
+        .collect();
+    for iface in interfaces.iter().filter(|iface| iface.isolate_clients) {
+        for network in &networks {
+            for (meta, addr) in [
+                (MetaType::IifName, Addr::Destination),
+                (MetaType::OifName, Addr::Source),
+            ] {
+                let rule = new_rule(chain)?
+                    .with_expr(Meta::new(meta))
+                    .with_expr(Cmp::new(CmpOp::Eq, ifname(&iface.name)?));
+                rules.push(
+                    match_network(rule, addr, network)?
+                        .with_expr(Immediate::new_verdict(VerdictKind::Drop)),
+                );
+            }
+        }
+    }
+    Ok(rules)
+}
+
+/// For one address family:
+/// `meta nfproto <f> iifname <wg> oifname <uplink> accept` and
+/// `meta nfproto <f> iifname <uplink> oifname <wg> ct state established,related accept`
+fn forward_rules(
+    chain: &Chain,
+    family: u8,
+    wg_iface: &str,
+    uplink: &str,
+) -> anyhow::Result<[Rule; 2]> {
+    let rule = |from: &str, to: &str| -> anyhow::Result<Rule> {
+        Ok(new_rule(chain)?
+            .with_expr(Meta::new(MetaType::NfProto))
+            .with_expr(Cmp::new(CmpOp::Eq, [family]))
+            .with_expr(Meta::new(MetaType::IifName))
+            .with_expr(Cmp::new(CmpOp::Eq, ifname(from)?))
+            .with_expr(Meta::new(MetaType::OifName))
+            .with_expr(Cmp::new(CmpOp::Eq, ifname(to)?)))
+    };
+
+    let outbound = rule(wg_iface, uplink)?.with_expr(Immediate::new_verdict(VerdictKind::Accept));
+    let inbound = established(rule(uplink, wg_iface)?)?
+        .with_expr(Immediate::new_verdict(VerdictKind::Accept));
+    Ok([outbound, inbound])
+}
+
+fn new_rule(chain: &Chain) -> anyhow::Result<Rule> {
+    Rule::new(chain).map_err(|e| anyhow!("Rule build error: {:?}", e))
+}
+
+/// Append `meta nfproto <f> <ip|ip6> <saddr|daddr> <network>`.
+fn match_network(rule: Rule, addr: Addr, network: &IpNet) -> anyhow::Result<Rule> {
+    let (family, field, mask, prefix) = match network.trunc() {
+        IpNet::V4(net) => (
+            NFPROTO_IPV4,
+            NetworkHeaderField::IPv4(match addr {
+                Addr::Source => IPv4HeaderField::Saddr,
+                Addr::Destination => IPv4HeaderField::Daddr,
+            }),
+            net.netmask().octets().to_vec(),
+            net.network().octets().to_vec(),
+        ),
+        IpNet::V6(net) => (
+            NFPROTO_IPV6,
+            NetworkHeaderField::IPv6(match addr {
+                Addr::Source => IPv6HeaderField::Saddr,
+                Addr::Destination => IPv6HeaderField::Daddr,
+            }),
+            net.netmask().octets().to_vec(),
+            net.network().octets().to_vec(),
+        ),
+    };
+    let zeros = vec![0u8; mask.len()];
+
+    Ok(rule
+        .with_expr(Meta::new(MetaType::NfProto))
+        .with_expr(Cmp::new(CmpOp::Eq, [family]))
+        .with_expr(HighLevelPayload::Network(field).build())
+        .with_expr(Bitwise::new(mask, zeros).map_err(|e| anyhow!("Rule build error: {:?}", e))?)
+        .with_expr(Cmp::new(CmpOp::Eq, prefix)))
+}
+
+/// Append `ct state established,related`.
+fn established(rule: Rule) -> anyhow::Result<Rule> {
+    use rustables::expr::ct::{ConnTrackState, Conntrack, ConntrackKey};
+
+    // ct state is a bitmask in host byte order
+    let states = (ConnTrackState::ESTABLISHED | ConnTrackState::RELATED).bits();
+    Ok(rule
+        .with_expr(Conntrack::new(ConntrackKey::State))
+        .with_expr(
+            Bitwise::new(states.to_ne_bytes(), 0u32.to_ne_bytes())
+                .map_err(|e| anyhow!("Rule build error: {:?}", e))?,
+        )
+        .with_expr(Cmp::new(CmpOp::Neq, 0u32.to_ne_bytes())))
+}
+
+/// An interface name as nftables compares it: NUL-terminated, i.e. exactly.
+fn ifname(name: &str) -> anyhow::Result<Vec<u8>> {
+    if name.is_empty() || name.len() >= IFNAMSIZ {
+        anyhow::bail!("Invalid interface name {name:?}");
+    }
+    let mut bytes = name.as_bytes().to_vec();
+    bytes.push(0);
+    Ok(bytes)
+}
+
+/// Base chains of other tables that drop forwarded packets by default, as
+/// `<family> <table> <chain>`, e.g. `ip filter FORWARD` from iptables-nft
+/// under Docker. The accept rules of our own forward chain can't override
+/// them, so VPN traffic is dropped there unless they accept it.
+///
+/// rustables reads every chain policy as accept, so this dumps the chains
+/// over nfnetlink directly.
+pub fn forward_drop_chains() -> anyhow::Result<Vec<String>> {
+    use netlink_sys::{protocols::NETLINK_NETFILTER, Socket, SocketAddr};
+
+    let socket = Socket::new(NETLINK_NETFILTER)?;
+    socket.connect(&SocketAddr::new(0, 0))?;
+    // nlmsghdr, then nfgenmsg for every family
+    let mut request = Vec::with_capacity(20);
+    request.extend(20u32.to_ne_bytes());
+    request.extend(((NFNL_SUBSYS_NFTABLES << 8) | NFT_MSG_GETCHAIN).to_ne_bytes());
+    request.extend((NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+    request.extend([0u8; 8]);
+    request.extend([0u8; 4]);
+    socket.send(&request, 0)?;
+
+    let mut chains = Vec::new();
+    loop {
+        let (buf, _) = socket.recv_from_full()?;
+        for (kind, payload) in netlink_messages(&buf) {
+            match kind {
+                NLMSG_DONE => return Ok(chains),
+                NLMSG_ERROR => {
+                    let errno = payload
+                        .get(..4)
+                        .map_or(0, |b| i32::from_ne_bytes(b.try_into().unwrap()));
+                    if errno != 0 {
+                        anyhow::bail!(
+                            "Listing nftables chains failed: {}",
+                            std::io::Error::from_raw_os_error(-errno)
+                        );
+                    }
+                }
+                _ => chains.extend(drop_forward_chain(payload)),
+            }
+        }
+    }
+}
+
+/// The `(type, payload)` of each netlink message in `buf`.
+fn netlink_messages(mut buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut messages = Vec::new();
+    while buf.len() >= 16 {
+        let len = u32::from_ne_bytes(buf[..4].try_into().unwrap()) as usize;
+        if len < 16 || len > buf.len() {
+            break;
+        }
+        messages.push((u16::from_ne_bytes([buf[4], buf[5]]), &buf[16..len]));
+        buf = &buf[((len + 3) & !3).min(buf.len())..];
+    }
+    messages
+}
+
+/// The `(type, value)` of each netlink attribute in `buf`.
+fn netlink_attributes(mut buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attributes = Vec::new();
+    while buf.len() >= 4 {
+        let len = u16::from_ne_bytes([buf[0], buf[1]]) as usize;
+        if len < 4 || len > buf.len() {
+            break;
+        }
+        // Without the nested and byte order flags
+        let kind = u16::from_ne_bytes([buf[2], buf[3]]) & 0x3fff;
+        attributes.push((kind, &buf[4..len]));
+        buf = &buf[((len + 3) & !3).min(buf.len())..];
+    }
+    attributes
+}
+
+/// `<family> <table> <chain>` if the chain in this `NFT_MSG_NEWCHAIN`
+/// payload is an `inet`, `ip` or `ip6` base chain outside our table, hooked
+/// at forward with policy drop.
+fn drop_forward_chain(payload: &[u8]) -> Option<String> {
+    let family = match payload.first()? {
+        1 => "inet",
+        2 => "ip",
+        10 => "ip6",
+        _ => return None,
+    };
+    let be_u32 = |value: &[u8]| value.try_into().ok().map(u32::from_be_bytes);
+    let string = |value: &[u8]| {
+        String::from_utf8_lossy(value)
+            .trim_end_matches('\0')
+            .to_string()
+    };
+    let (mut table, mut name, mut hook, mut policy) = (None, None, None, None);
+    for (kind, value) in netlink_attributes(payload.get(4..)?) {
+        match kind {
+            NFTA_CHAIN_TABLE => table = Some(string(value)),
+            NFTA_CHAIN_NAME => name = Some(string(value)),
+            NFTA_CHAIN_HOOK => {
+                hook = netlink_attributes(value)
+                    .into_iter()
+                    .find(|(kind, _)| *kind == NFTA_HOOK_HOOKNUM)
+                    .and_then(|(_, value)| be_u32(value))
+            }
+            NFTA_CHAIN_POLICY => policy = be_u32(value),
+            _ => {}
+        }
+    }
+    let table = table.filter(|table| table != TABLE_NAME)?;
+    (hook == Some(NF_INET_FORWARD) && policy == Some(NF_DROP))
+        .then(|| format!("{family} {table} {}", name.unwrap_or_default()))
+}
+
 /// Remove the wg_easy_nat nftables table atomically.
 pub fn teardown_nat() -> anyhow::Result<()> {
-    use rustables::{Batch, MsgType, ProtocolFamily, Table};
-
     let mut batch = Batch::new();
     let table = Table::new(ProtocolFamily::Inet).with_name(TABLE_NAME.to_string());
     batch.add(&table, MsgType::Del);
@@ -61,3 +590,88 @@ pub fn teardown_nat() -> anyhow::Result<()> {
     info!("nftables NAT table {} removed", TABLE_NAME);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_forward_chain() {
+        let attribute = |kind: u16, value: &[u8]| {
+            let mut buf = ((4 + value.len()) as u16).to_ne_bytes().to_vec();
+            buf.extend(kind.to_ne_bytes());
+            buf.extend(value);
+            buf.resize((buf.len() + 3) & !3, 0);
+            buf
+        };
+        let chain = |family: u8, table: &str, hook: u32, policy: u32| {
+            let mut payload = vec![family, 0, 0, 0];
+            payload.extend(attribute(NFTA_CHAIN_TABLE, format!("{table}\0").as_bytes()));
+            payload.extend(attribute(NFTA_CHAIN_NAME, b"FORWARD\0"));
+            let hooknum = attribute(NFTA_HOOK_HOOKNUM, &hook.to_be_bytes());
+            payload.extend(attribute(NFTA_CHAIN_HOOK | 0x8000, &hooknum));
+            payload.extend(attribute(NFTA_CHAIN_POLICY, &policy.to_be_bytes()));
+            payload
+        };
+
+        assert_eq!(
+            drop_forward_chain(&chain(2, "filter", NF_INET_FORWARD, NF_DROP)).as_deref(),
+            Some("ip filter FORWARD")
+        );
+        // Accept policy, another hook, our own table, a bridge chain
+        assert_eq!(
+            drop_forward_chain(&chain(2, "filter", NF_INET_FORWARD, 1)),
+            None
+        );
+        assert_eq!(drop_forward_chain(&chain(2, "filter", 1, NF_DROP)), None);
+        assert_eq!(
+            drop_forward_chain(&chain(1, TABLE_NAME, NF_INET_FORWARD, NF_DROP)),
+            None
+        );
+        assert_eq!(
+            drop_forward_chain(&chain(7, "filter", NF_INET_FORWARD, NF_DROP)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_acl_groups() {
+        let rule = |cidr: &str| AclRule {
+            action: AclAction::Allow,
+            cidr: cidr.to_string(),
+            protocol: None,
+            ports: None,
+        };
+        let acl = |source: &str, rules: Vec<AclRule>| ClientAcl {
+            sources: vec![source.parse().unwrap()],
+            rules,
+        };
+        let iface = |acls| NatInterface {
+            name: "wg0".to_string(),
+            networks: Vec::new(),
+            isolate_clients: false,
+            exempt: Vec::new(),
+            acls,
+        };
+        let interfaces = [
+            iface(vec![
+                acl("10.8.0.2/32", vec![rule("10.0.0.0/8")]),
+                acl("10.8.0.3/32", vec![rule("0.0.0.0/0")]),
+                acl("10.8.0.4/32", Vec::new()),
+            ]),
+            iface(vec![acl("192.168.5.0/24", vec![rule("10.0.0.0/8")])]),
+        ];
+
+        let groups = acl_groups(&interfaces);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].0, [rule("10.0.0.0/8")]);
+        assert_eq!(
+            groups[0].1,
+            [
+                "10.8.0.2/32".parse::<IpNet>().unwrap(),
+                "192.168.5.0/24".parse().unwrap()
+            ]
+        );
+        assert_eq!(groups[1].1, ["10.8.0.3/32".parse::<IpNet>().unwrap()]);
+    }
+}
@@ -35,22 +35,11 @@ pub fn configure_interface(
 pub fn add_peer(
     name: &str,
     public_key_b64: &str,
-    preshared_key_b64: &str,
-    allowed_ips: &[&str],
+    preshared_key_b64: Option<&str>,
+    allowed_ips: &[String],
 ) -> anyhow::Result<()> {
     let iface = iface_name(name)?;
-    let pubkey =
-        Key::from_base64(public_key_b64).map_err(|e| anyhow!("Invalid public key: {}", e))?;
-    let psk =
-        Key::from_base64(preshared_key_b64).map_err(|e| anyhow!("Invalid preshared key: {}", e))?;
-
-    let mut peer = PeerConfigBuilder::new(&pubkey).set_preshared_key(psk);
-    for ip_str in allowed_ips {
-        let net: ipnet::IpNet = ip_str
-            .parse()
-            .map_err(|e| anyhow!("Invalid allowed IP {}: {}", ip_str, e))?;
-        peer = peer.add_allowed_ip(net.addr(), net.prefix_len());
-    }
+    let peer = peer_config(public_key_b64, preshared_key_b64, allowed_ips)?;
 
     DeviceUpdate::new()
         .add_peer(peer)
@@ -93,23 +82,38 @@ pub fn get_stats(name: &str) -> anyhow::Result<Vec<PeerStats>> {
 /// Bulk-sync all peers on startup.
 pub fn sync_peers(
     name: &str,
-    peers: &[(String, String, Vec<String>)], // (public_key, preshared_key, allowed_ips)
+    peers: &[(String, Option<String>, Vec<String>)], // (public_key, preshared_key, allowed_ips)
 ) -> anyhow::Result<()> {
     let iface = iface_name(name)?;
     let mut update = DeviceUpdate::new().replace_peers();
     for (pubkey_b64, psk_b64, allowed_ips) in peers {
-        let pubkey =
-            Key::from_base64(pubkey_b64).map_err(|e| anyhow!("Invalid public key: {}", e))?;
-        let psk = Key::from_base64(psk_b64).map_err(|e| anyhow!("Invalid preshared key: {}", e))?;
-        let mut peer = PeerConfigBuilder::new(&pubkey).set_preshared_key(psk);
-        for ip_str in allowed_ips {
-            let net: ipnet::IpNet = ip_str
-                .parse()
-                .map_err(|e| anyhow!("Invalid allowed IP {}: {}", ip_str, e))?;
-            peer = peer.add_allowed_ip(net.addr(), net.prefix_len());
-        }
-        update = update.add_peer(peer);
+        update = update.add_peer(peer_config(pubkey_b64, psk_b64.as_deref(), allowed_ips)?);
     }
     update.apply(&iface, Backend::Kernel)?;
     Ok(())
 }
+
+fn peer_config(
+    public_key_b64: &str,
+    preshared_key_b64: Option<&str>,
+    allowed_ips: &[String],
+) -> anyhow::Result<PeerConfigBuilder> {
+    let pubkey =
+        Key::from_base64(public_key_b64).map_err(|e| anyhow!("Invalid public key: {}", e))?;
+    let mut peer = PeerConfigBuilder::new(&pubkey).replace_allowed_ips();
+    peer = match preshared_key_b64 {
+        Some(psk_b64) => {
+            let psk =
+                Key::from_base64(psk_b64).map_err(|e| anyhow!("Invalid preshared key: {}", e))?;
+            peer.set_preshared_key(psk)
+        }
+        None => peer.unset_preshared_key(),
+    };
+    for ip_str in allowed_ips {
+        let net: ipnet::IpNet = ip_str
+            .parse()
+            .map_err(|e| anyhow!("Invalid allowed IP {}: {}", ip_str, e))?;
+        peer = peer.add_allowed_ip(net.addr(), net.prefix_len());
+    }
+    Ok(peer)
+}